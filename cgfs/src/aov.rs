@@ -0,0 +1,260 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgb, Rgb32FImage, RgbImage};
+
+use crate::color::{Color, FloatColor};
+use crate::math::*;
+
+pub type Gray32FImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+pub type ObjectIdImage = ImageBuffer<Luma<u32>, Vec<u32>>;
+pub type SampleCountImage = ImageBuffer<Luma<u32>, Vec<u32>>;
+
+/// Auxiliary output captured from the primary hit of every pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance along the camera's view axis.
+    Depth,
+    /// World space surface normal.
+    Normal,
+    /// Index of the object, see [`RayTracer::object`](crate::RayTracer::object).
+    ObjectId,
+    /// Surface color before lighting.
+    Albedo,
+    /// World space hit position.
+    Position,
+    /// Number of samples taken for the pixel.
+    SampleCount,
+}
+
+/// Buffers filled by [`RayTracer::render`](crate::RayTracer::render) for
+/// every enabled [`Aov`]. Pixels whose primary ray misses hold infinite
+/// depth, [`AovBuffers::NO_OBJECT`] and zero everywhere else.
+#[derive(Debug, Clone, Default)]
+pub struct AovBuffers {
+    pub depth: Option<Gray32FImage>,
+    pub normal: Option<Rgb32FImage>,
+    pub object_id: Option<ObjectIdImage>,
+    pub albedo: Option<Rgb32FImage>,
+    pub position: Option<Rgb32FImage>,
+    pub sample_count: Option<SampleCountImage>,
+}
+
+/// What the primary ray of a pixel hit.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AovSample {
+    pub depth: f64,
+    pub normal: Vector,
+    pub object: usize,
+    pub albedo: Color,
+    pub position: Point,
+}
+
+impl AovBuffers {
+    pub const NO_OBJECT: u32 = u32::MAX;
+
+    pub fn is_enabled(&self, aov: Aov) -> bool {
+        match aov {
+            Aov::Depth => self.depth.is_some(),
+            Aov::Normal => self.normal.is_some(),
+            Aov::ObjectId => self.object_id.is_some(),
+            Aov::Albedo => self.albedo.is_some(),
+            Aov::Position => self.position.is_some(),
+            Aov::SampleCount => self.sample_count.is_some(),
+        }
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        [Aov::Depth, Aov::Normal, Aov::ObjectId, Aov::Albedo, Aov::Position, Aov::SampleCount]
+            .into_iter()
+            .any(|aov| self.is_enabled(aov))
+    }
+
+    /// Allocates the buffer for `aov`, keeping it if it already exists.
+    pub fn enable(&mut self, aov: Aov, width: u32, height: u32) {
+        if self.is_enabled(aov) {
+            return;
+        }
+        match aov {
+            Aov::Depth => self.depth = Some(ImageBuffer::new(width, height)),
+            Aov::Normal => self.normal = Some(ImageBuffer::new(width, height)),
+            Aov::ObjectId => self.object_id = Some(ImageBuffer::new(width, height)),
+            Aov::Albedo => self.albedo = Some(ImageBuffer::new(width, height)),
+            Aov::Position => self.position = Some(ImageBuffer::new(width, height)),
+            Aov::SampleCount => self.sample_count = Some(ImageBuffer::new(width, height)),
+        }
+    }
+
+    pub fn disable(&mut self, aov: Aov) {
+        match aov {
+            Aov::Depth => self.depth = None,
+            Aov::Normal => self.normal = None,
+            Aov::ObjectId => self.object_id = None,
+            Aov::Albedo => self.albedo = None,
+            Aov::Position => self.position = None,
+            Aov::SampleCount => self.sample_count = None,
+        }
+    }
+
+    /// Reallocates every enabled buffer that doesn't match the given size.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        fn fit<P: Pixel>(buf: &mut Option<ImageBuffer<P, Vec<P::Subpixel>>>, width: u32, height: u32) {
+            if let Some(b) = buf {
+                if b.dimensions() != (width, height) {
+                    *b = ImageBuffer::new(width, height);
+                }
+            }
+        }
+        fit(&mut self.depth, width, height);
+        fit(&mut self.normal, width, height);
+        fit(&mut self.object_id, width, height);
+        fit(&mut self.albedo, width, height);
+        fit(&mut self.position, width, height);
+        fit(&mut self.sample_count, width, height);
+    }
+
+    pub(crate) fn record(&mut self, x: u32, y: u32, sample: Option<AovSample>) {
+        if let Some(depth) = &mut self.depth {
+            let d = sample.map_or(f32::INFINITY, |s| s.depth as f32);
+            depth.put_pixel(x, y, Luma([d]));
+        }
+        if let Some(normal) = &mut self.normal {
+            let n = sample.map_or(vector(0., 0., 0.), |s| s.normal);
+            normal.put_pixel(x, y, Rgb([n.x as f32, n.y as f32, n.z as f32]));
+        }
+        if let Some(object_id) = &mut self.object_id {
+            let id = sample.map_or(Self::NO_OBJECT, |s| s.object as u32);
+            object_id.put_pixel(x, y, Luma([id]));
+        }
+        if let Some(albedo) = &mut self.albedo {
+            let c = sample.map_or(FloatColor::BLACK, |s| FloatColor::from(s.albedo));
+            albedo.put_pixel(x, y, Rgb([c.r as f32, c.g as f32, c.b as f32]));
+        }
+        if let Some(position) = &mut self.position {
+            let p = sample.map_or(point(0., 0., 0.), |s| s.position);
+            position.put_pixel(x, y, Rgb([p.x as f32, p.y as f32, p.z as f32]));
+        }
+    }
+
+    pub(crate) fn record_samples(&mut self, x: u32, y: u32, samples: u32) {
+        if let Some(sample_count) = &mut self.sample_count {
+            sample_count.put_pixel(x, y, Luma([samples]));
+        }
+    }
+
+    /// Depth remapped so the nearest hit is black and the farthest is white.
+    /// Misses are white too.
+    pub fn depth_image(&self) -> Option<GrayImage> {
+        let depth = self.depth.as_ref()?;
+        let finite = depth.pixels().map(|p| p.0[0]).filter(|d| d.is_finite());
+        let (min, max) = finite.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| (min.min(d), max.max(d)));
+        let range = if max > min { max - min } else { 1. };
+
+        Some(ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+            let d = depth.get_pixel(x, y).0[0];
+            let v = if d.is_finite() { (d - min) / range } else { 1. };
+            Luma([(v * 255.).round() as u8])
+        }))
+    }
+
+    /// Normals mapped from `[-1, 1]` to `[0, 255]` per axis.
+    pub fn normal_image(&self) -> Option<RgbImage> {
+        let normal = self.normal.as_ref()?;
+        Some(ImageBuffer::from_fn(normal.width(), normal.height(), |x, y| {
+            let n = normal.get_pixel(x, y).0;
+            Rgb(n.map(|c| ((c * 0.5 + 0.5) * 255.).round().clamp(0., 255.) as u8))
+        }))
+    }
+
+    /// A distinct color per object, black where nothing was hit.
+    pub fn object_id_image(&self) -> Option<RgbImage> {
+        let object_id = self.object_id.as_ref()?;
+        Some(ImageBuffer::from_fn(object_id.width(), object_id.height(), |x, y| {
+            let id = object_id.get_pixel(x, y).0[0];
+            if id == Self::NO_OBJECT {
+                return Rgb([0, 0, 0]);
+            }
+            let h = (id.wrapping_add(1)).wrapping_mul(0x9e3779b9);
+            Rgb([(h >> 24) as u8 | 0x40, (h >> 16) as u8 | 0x40, (h >> 8) as u8 | 0x40])
+        }))
+    }
+
+    /// Heatmap of the sample counts, from blue for the fewest samples to red
+    /// for the most.
+    pub fn sample_count_image(&self) -> Option<RgbImage> {
+        let sample_count = self.sample_count.as_ref()?;
+        let (min, max) = sample_count.pixels().map(|p| p.0[0])
+            .fold((u32::MAX, 0), |(min, max), n| (min.min(n), max.max(n)));
+        let range = max.saturating_sub(min).max(1) as f32;
+
+        Some(ImageBuffer::from_fn(sample_count.width(), sample_count.height(), |x, y| {
+            let t = (sample_count.get_pixel(x, y).0[0] - min) as f32 / range;
+            let ramp = |c: f32| ((1. - (t - c).abs() * 2.).clamp(0., 1.) * 255.) as u8;
+            Rgb([ramp(1.), ramp(0.5), ramp(0.)])
+        }))
+    }
+
+    pub fn albedo_image(&self) -> Option<RgbImage> {
+        let albedo = self.albedo.as_ref()?;
+        Some(ImageBuffer::from_fn(albedo.width(), albedo.height(), |x, y| {
+            let c = albedo.get_pixel(x, y).0;
+            let c = FloatColor::from(c).to_color();
+            Rgb([c.r, c.g, c.b])
+        }))
+    }
+}
+
+/// Writes a 1 or 3 channel float image as a Portable Float Map (`.pfm`).
+pub fn save_pfm<P, Q>(image: &ImageBuffer<P, Vec<f32>>, path: Q) -> Result<(), String>
+where
+    P: Pixel<Subpixel = f32>,
+    Q: AsRef<Path>,
+{
+    let header = match P::CHANNEL_COUNT {
+        1 => "Pf",
+        3 => "PF",
+        n => return Err(format!("pfm cannot store {n} channels")),
+    };
+    let path = path.as_ref();
+    let file = File::create(path).map_err(|e| format!("could not create `{}`: {e}", path.display()))?;
+    let mut out = BufWriter::new(file);
+
+    let write = |out: &mut BufWriter<File>| -> std::io::Result<()> {
+        // negative scale marks little endian data
+        write!(out, "{header}\n{} {}\n-1.0\n", image.width(), image.height())?;
+        // rows are stored bottom to top
+        for row in image.as_raw().chunks(image.width() as usize * P::CHANNEL_COUNT as usize).rev() {
+            for v in row {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    };
+    write(&mut out).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_normalization() {
+        let mut aovs = AovBuffers::default();
+        aovs.enable(Aov::Depth, 3, 1);
+        let sample = |depth| Some(AovSample {
+            depth,
+            normal: vector(0., 0., -1.),
+            object: 0,
+            albedo: Color::new(0, 0, 0),
+            position: point(0., 0., 0.),
+        });
+        aovs.record(0, 0, sample(2.));
+        aovs.record(1, 0, sample(4.));
+        aovs.record(2, 0, None);
+
+        let img = aovs.depth_image().unwrap();
+        assert_eq!(img.as_raw(), &vec![0, 255, 255]);
+        assert!(aovs.normal_image().is_none());
+    }
+}
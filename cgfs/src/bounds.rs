@@ -0,0 +1,102 @@
+use crate::math::*;
+use crate::transform::Transform;
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    /// Box that contains nothing; the identity of [`Aabb::union`].
+    pub const EMPTY: Aabb = Aabb {
+        min: point(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        max: point(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    };
+
+    pub fn new(min: Point, max: Point) -> Self {
+        Self {
+            min, max
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            point(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            point(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        )
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            point(a.x, a.y, a.z), point(b.x, a.y, a.z), point(a.x, b.y, a.z), point(b.x, b.y, a.z),
+            point(a.x, a.y, b.z), point(b.x, a.y, b.z), point(a.x, b.y, b.z), point(b.x, b.y, b.z),
+        ]
+    }
+
+    /// Box around this one after applying `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        if self.is_empty() || transform.is_identity() {
+            return *self;
+        }
+        self.corners()
+            .into_iter()
+            .map(|c| transform.point(c))
+            .fold(Aabb::EMPTY, |b, p| b.union(&Aabb::new(p, p)))
+    }
+
+    /// Slab test: whether the ray enters the box for some `t` inside the
+    /// bounds.
+    pub fn intersects(&self, origin: Point, ray: Vector, t_bounds: (f64, f64)) -> bool {
+        let (mut t_min, mut t_max) = t_bounds;
+        for axis in 0..3 {
+            let inv = 1. / ray[axis];
+            let mut t0 = (self.min[axis] - origin[axis]) * inv;
+            let mut t1 = (self.max[axis] - origin[axis]) * inv;
+            if inv < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN (ray parallel to and on a slab boundary) leaves the bounds untouched
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slab_test() {
+        let b = Aabb::new(point(-1., -1., -1.), point(1., 1., 1.));
+        assert!(b.intersects(point(0., 0., -5.), vector(0., 0., 1.), (0., f64::INFINITY)));
+        assert!(!b.intersects(point(0., 0., -5.), vector(0., 0., 1.), (0., 3.)));
+        assert!(!b.intersects(point(0., 2., -5.), vector(0., 0., 1.), (0., f64::INFINITY)));
+        assert!(b.intersects(point(0., 0., 0.), vector(1., 0., 0.), (0., f64::INFINITY)));
+    }
+
+    #[test]
+    fn transformed_box() {
+        let b = Aabb::new(point(-1., -1., -1.), point(1., 1., 1.));
+        let t = Transform::from_parts(vector(5., 0., 0.), vector(0., 0., 45.), vector(1., 1., 1.)).unwrap();
+        let moved = b.transformed(&t);
+        let s = 2f64.sqrt();
+        assert!((moved.max.x - (5. + s)).abs() < 1e-9 && (moved.max.y - s).abs() < 1e-9);
+        assert!(Aabb::EMPTY.transformed(&t).is_empty());
+    }
+}
@@ -1,8 +1,5 @@
+use crate::canvas::Canvas;
 use crate::math::*;
-use cgmath::prelude::*;
-use cgmath::Quaternion;
-
-const ORIGIN_VEC: Vector = vector(0., 0., 1.);
 
 #[derive(Debug, Clone)]
 pub struct Camera {
@@ -37,3 +34,16 @@ impl Default for Viewport {
         }
     }
 }
+
+impl Viewport {
+    /// One unit wide at distance `1`, with the aspect ratio of `canvas`.
+    pub fn fit(canvas: &Canvas) -> Self {
+        let ratio = canvas.width() as f64 / canvas.height() as f64;
+        let width = 1.;
+        Self {
+            width,
+            height: width / ratio,
+            distance: 1.,
+        }
+    }
+}
@@ -1,15 +1,21 @@
-use image::{RgbImage, Rgb};
+use image::{GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
 use crate::color::Color;
 
 #[derive(Debug, Clone)]
 pub struct Canvas {
     pub image: RgbImage,
+    /// Straight (not premultiplied) alpha of every pixel, `None` while the
+    /// canvas is opaque. Renders only have it when the background is
+    /// transparent.
+    pub alpha: Option<GrayImage>,
 }
 
 impl Canvas {
+    /// Opaque black canvas.
     pub fn new(x: u32, y: u32) -> Self {
         Self {
             image: RgbImage::new(x, y),
+            alpha: None,
         }
     }
 
@@ -20,37 +26,253 @@ impl Canvas {
         self.image.height()
     }
 
+    /// The centered coordinates span `min_x..max_x` and `min_y..max_y`, with
+    /// the origin in the middle and y pointing up. Odd sizes are symmetric,
+    /// even ones have one more column left of the origin than right of it
+    /// and one more row below it than above.
     pub fn max_x(&self) -> i32 {
-        self.image.width() as i32 / 2
+        self.min_x() + self.image.width() as i32
     }
     pub fn min_x(&self) -> i32 {
         -(self.image.width() as i32 / 2)
     }
 
     pub fn max_y(&self) -> i32 {
-        self.image.height() as i32 / 2
+        self.min_y() + self.image.height() as i32
     }
     pub fn min_y(&self) -> i32 {
         -(self.image.height() as i32 / 2)
     }
 
+    /// Converts centered canvas coordinates into image coordinates, or `None`
+    /// if they fall outside the image.
+    pub fn to_image_coords(&self, x: i32, y: i32) -> Option<(u32, u32)> {
+        let new_x = x as i64 - self.min_x() as i64;
+        let new_y = self.max_y() as i64 - 1 - y as i64;
+
+        let new_x: u32 = new_x.try_into().ok()?;
+        let new_y: u32 = new_y.try_into().ok()?;
+
+        (new_x < self.width() && new_y < self.height()).then_some((new_x, new_y))
+    }
+
+    /// Inverse of [`Canvas::to_image_coords`], e.g. to map a mouse click back
+    /// onto the canvas.
+    pub fn from_image_coords(&self, x: u32, y: u32) -> (i32, i32) {
+        (
+            x as i32 + self.min_x(),
+            self.max_y() - 1 - y as i32,
+        )
+    }
+
+    /// Sets the pixel at centered coordinates `(x, y)`, doing nothing if it's
+    /// outside the canvas.
     pub fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
-        let new_x = self.image.width() as i32 / 2 + x;
-        let new_y = self.image.height() as i32 / 2 - y - 1;
+        if let Some((x, y)) = self.to_image_coords(x, y) {
+            self.set_rgba(x, y, Rgba([color.r, color.g, color.b, 255]));
+        }
+    }
+
+    /// Like [`Canvas::put_pixel`], but fails on pixels outside the canvas.
+    pub fn try_put_pixel(&mut self, x: i32, y: i32, color: Color) -> Result<(), String> {
+        let (new_x, new_y) = self.to_image_coords(x, y)
+            .ok_or_else(|| format!("pixel ({x}, {y}) is outside the canvas"))?;
+
+        self.set_rgba(new_x, new_y, Rgba([color.r, color.g, color.b, 255]));
+        Ok(())
+    }
+
+    /// Composites `color` over the pixel at centered coordinates `(x, y)`
+    /// with `coverage` in `[0, 1]` as its alpha, e.g. for anti-aliasing.
+    /// Does nothing outside the canvas.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Color, coverage: f64) {
+        if let Some((x, y)) = self.to_image_coords(x, y) {
+            let alpha = (coverage.clamp(0., 1.) * 255.).round() as u8;
+            let p = over(Rgba([color.r, color.g, color.b, alpha]), self.rgba(x, y));
+            self.set_rgba(x, y, p);
+        }
+    }
+
+    /// Color of the pixel at centered coordinates `(x, y)`, ignoring alpha.
+    pub fn get_pixel(&self, x: i32, y: i32) -> Option<Color> {
+        let (x, y) = self.to_image_coords(x, y)?;
+        let Rgb([r, g, b]) = *self.image.get_pixel(x, y);
+        Some(Color::new(r, g, b))
+    }
+
+    /// Fills the whole canvas with an opaque color.
+    pub fn clear(&mut self, color: Color) {
+        for p in self.image.pixels_mut() {
+            *p = Rgb([color.r, color.g, color.b]);
+        }
+        self.alpha = None;
+    }
+
+    /// Fills the rectangle between two opposite corners, both included,
+    /// clipped to the canvas.
+    pub fn fill_rect(&mut self, (x0, y0): (i32, i32), (x1, y1): (i32, i32), color: Color) {
+        let x_range = x0.min(x1).max(self.min_x())..=x0.max(x1).min(self.max_x() - 1);
+        let y_range = y0.min(y1).max(self.min_y())..=y0.max(y1).min(self.max_y() - 1);
+        for y in y_range {
+            for x in x_range.clone() {
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Copies `src` so that its origin lands on `(x, y)`, replacing the
+    /// pixels underneath including their alpha. Whatever falls outside the
+    /// canvas is dropped.
+    pub fn blit(&mut self, src: &Canvas, x: i32, y: i32) {
+        self.draw_canvas(src, x, y, |top, _| top);
+    }
+
+    /// Composites `top` over the canvas so that its origin lands on `(x, y)`.
+    /// Whatever falls outside the canvas is dropped.
+    pub fn composite(&mut self, top: &Canvas, x: i32, y: i32) {
+        self.draw_canvas(top, x, y, over);
+    }
 
-        //let new_x: u32 = match new_x.try_into() {
-        //    Ok(x) => x,
-        //    Err(_) => return,
-        //};
+    /// The canvas composited over a solid color, without alpha.
+    pub fn flatten(&self, background: Color) -> RgbImage {
+        let background = Rgba([background.r, background.g, background.b, 255]);
+        RgbImage::from_fn(self.width(), self.height(), |x, y| {
+            let Rgba([r, g, b, _]) = over(self.rgba(x, y), background);
+            Rgb([r, g, b])
+        })
+    }
+
+    /// Color and alpha of every pixel.
+    pub fn to_rgba(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width(), self.height(), |x, y| self.rgba(x, y))
+    }
+
+    /// Combines every pixel of `src` with the one it lands on when its origin
+    /// is moved to `(x, y)`.
+    fn draw_canvas<F: Fn(Rgba<u8>, Rgba<u8>) -> Rgba<u8>>(&mut self, src: &Canvas, x: i32, y: i32, f: F) {
+        for (sx, sy, _) in src.image.enumerate_pixels() {
+            let (cx, cy) = src.from_image_coords(sx, sy);
+            if let Some((ix, iy)) = self.to_image_coords(x.saturating_add(cx), y.saturating_add(cy)) {
+                let p = f(src.rgba(sx, sy), self.rgba(ix, iy));
+                self.set_rgba(ix, iy, p);
+            }
+        }
+    }
+
+    fn rgba(&self, x: u32, y: u32) -> Rgba<u8> {
+        let Rgb([r, g, b]) = *self.image.get_pixel(x, y);
+        let a = self.alpha.as_ref().map_or(255, |alpha| alpha.get_pixel(x, y)[0]);
+        Rgba([r, g, b, a])
+    }
+
+    /// Sets a pixel by image coordinates, adding the alpha channel the first
+    /// time a pixel isn't opaque.
+    fn set_rgba(&mut self, x: u32, y: u32, Rgba([r, g, b, a]): Rgba<u8>) {
+        self.image.put_pixel(x, y, Rgb([r, g, b]));
+        if a < 255 && self.alpha.is_none() {
+            self.alpha = Some(GrayImage::from_pixel(self.width(), self.height(), Luma([255])));
+        }
+        if let Some(alpha) = &mut self.alpha {
+            alpha.put_pixel(x, y, Luma([a]));
+        }
+    }
+}
+
+/// Porter-Duff "over" of two straight alpha pixels.
+pub fn over(top: Rgba<u8>, bottom: Rgba<u8>) -> Rgba<u8> {
+    let alpha = |p: Rgba<u8>| p[3] as f64 / 255.;
+    let (ta, ba) = (alpha(top), alpha(bottom));
+    let a = ta + ba * (1. - ta);
+    if a <= 0. {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let channel = |i: usize| {
+        let c = (top[i] as f64 * ta + bottom[i] as f64 * ba * (1. - ta)) / a;
+        c.round() as u8
+    };
+    Rgba([channel(0), channel(1), channel(2), (a * 255.).round() as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compositing() {
+        let red = Rgba([255, 0, 0, 255]);
+        let half_blue = Rgba([0, 0, 255, 128]);
+        let clear = Rgba([0, 0, 0, 0]);
+        assert_eq!(over(half_blue, red), Rgba([127, 0, 128, 255]));
+        assert_eq!(over(clear, red), red);
+        assert_eq!(over(red, half_blue), red);
+        assert_eq!(over(half_blue, clear), half_blue);
+        assert_eq!(over(half_blue, half_blue), Rgba([0, 0, 255, 192]));
+
+        // a 2x2 canvas has its origin at the bottom right pixel
+        let mut top = Canvas::new(2, 2);
+        top.blend_pixel(-1, 0, Color::new(0, 0, 255), 128. / 255.);
+        top.blend_pixel(0, 0, Color::new(0, 0, 255), 1.);
+        assert!(top.alpha.is_none());
+        top.blit(&Canvas { alpha: Some(GrayImage::new(2, 2)), ..Canvas::new(2, 2) }, 0, 0);
+        top.blend_pixel(-1, 0, Color::new(0, 0, 255), 128. / 255.);
+        assert_eq!(top.to_rgba().get_pixel(0, 0), &half_blue);
+
+        let mut canvas = Canvas::new(4, 4);
+        canvas.composite(&top, 2, 1);
+        assert_eq!(canvas.to_rgba().get_pixel(3, 0), &Rgba([0, 0, 128, 255]));
+        assert_eq!(canvas.to_rgba().get_pixel(2, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(canvas.to_rgba().get_pixel(3, 1), &Rgba([0, 0, 0, 255]));
+        assert!(canvas.alpha.is_none());
+
+        canvas.blit(&Canvas { alpha: Some(GrayImage::new(1, 1)), ..Canvas::new(1, 1) }, -2, 1);
+        assert_eq!(canvas.to_rgba().get_pixel(0, 0), &clear);
+        assert_eq!(*canvas.flatten(Color::new(10, 20, 30)).get_pixel(0, 0), Rgb([10, 20, 30]));
+    }
+
+    #[test]
+    fn centered_coordinates() {
+        for (w, h) in [(4, 4), (5, 3), (1, 2)] {
+            let canvas = Canvas::new(w, h);
+            assert_eq!(canvas.max_x() - canvas.min_x(), w as i32);
+            assert_eq!(canvas.max_y() - canvas.min_y(), h as i32);
+            assert_eq!(canvas.to_image_coords(canvas.min_x(), canvas.max_y() - 1), Some((0, 0)));
+            assert_eq!(canvas.to_image_coords(canvas.max_x() - 1, canvas.min_y()), Some((w - 1, h - 1)));
+            assert_eq!(canvas.to_image_coords(canvas.max_x(), 0), None);
+            assert_eq!(canvas.to_image_coords(0, canvas.min_y() - 1), None);
+            for (x, y, _) in canvas.image.enumerate_pixels() {
+                let (cx, cy) = canvas.from_image_coords(x, y);
+                assert_eq!(canvas.to_image_coords(cx, cy), Some((x, y)));
+            }
+        }
+        // odd sizes are symmetric around the origin
+        let canvas = Canvas::new(5, 3);
+        assert_eq!((canvas.min_x(), canvas.max_x(), canvas.min_y(), canvas.max_y()), (-2, 3, -1, 2));
+        assert_eq!(canvas.to_image_coords(0, 0), Some((2, 1)));
+    }
 
-        //let new_y: u32 = match new_y.try_into() {
-        //    Ok(y) => y,
-        //    Err(_) => return,
-        //};
+    #[test]
+    fn clipped_drawing() {
+        let (red, blue) = (Color::new(255, 0, 0), Color::new(0, 0, 255));
+        let mut canvas = Canvas::new(4, 4);
+        canvas.put_pixel(i32::MAX, i32::MIN, red);
+        assert!(canvas.try_put_pixel(2, 0, red).is_err());
+        assert!(canvas.try_put_pixel(1, 1, red).is_ok());
+        assert_eq!(canvas.get_pixel(1, 1), Some(red));
+        assert_eq!(canvas.get_pixel(2, 0), None);
 
-        let new_x: u32 = new_x.try_into().unwrap();
-        let new_y: u32 = new_y.try_into().unwrap();
+        canvas.clear(blue);
+        canvas.fill_rect((10, -10), (0, 0), red);
+        let reds = canvas.image.pixels().filter(|p| p.0 == [255, 0, 0]).count();
+        assert_eq!(reds, 6);
+        assert_eq!(canvas.get_pixel(-1, 0), Some(blue));
 
-        self.image.put_pixel(new_x, new_y, Rgb([color.r, color.g, color.b]));
+        let mut dot = Canvas::new(3, 3);
+        dot.clear(red);
+        canvas.clear(blue);
+        canvas.blit(&dot, 1, 1);
+        assert_eq!(canvas.get_pixel(0, 0), Some(red));
+        assert_eq!(canvas.get_pixel(1, 1), Some(red));
+        assert_eq!(canvas.get_pixel(-1, 0), Some(blue));
+        assert_eq!(canvas.image.pixels().filter(|p| p.0 == [255, 0, 0]).count(), 4);
     }
 }
@@ -17,6 +17,82 @@ impl Color {
             r, g, b
         }
     }
+
+    /// Parses a CSS hex color, `#rgb` or `#rrggbb`. The `#` is optional.
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let err = || format!("invalid hex color `{hex}`");
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        let channel = |i: usize, len: usize| u8::from_str_radix(&digits[i * len..(i + 1) * len], 16);
+        match digits.len() {
+            3 => Ok(Color::new(channel(0, 1).unwrap() * 17, channel(1, 1).unwrap() * 17, channel(2, 1).unwrap() * 17)),
+            6 => Ok(Color::new(channel(0, 2).unwrap(), channel(1, 2).unwrap(), channel(2, 2).unwrap())),
+            _ => Err(err()),
+        }
+    }
+
+    /// Lowercase `#rrggbb` string.
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Looks up one of the CSS named colors, ignoring case.
+    pub fn named(name: &str) -> Option<Self> {
+        NAMED_COLORS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, c)| c)
+    }
+
+    /// Decodes the sRGB channels into linear light.
+    pub fn to_linear(self) -> FloatColor {
+        FloatColor::from(self).srgb_to_linear()
+    }
+
+    /// Encodes linear light into sRGB channels.
+    pub fn from_linear(c: FloatColor) -> Self {
+        c.linear_to_srgb().to_nearest_color()
+    }
+
+    /// Hue in degrees, saturation and value in `[0, 1]`.
+    pub fn from_hsv(h: f64, s: f64, v: f64) -> Self {
+        FloatColor::from_hsv(h, s, v).to_nearest_color()
+    }
+
+    pub fn to_hsv(self) -> (f64, f64, f64) {
+        FloatColor::from(self).to_hsv()
+    }
+
+    /// Hue in degrees, saturation and lightness in `[0, 1]`.
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        FloatColor::from_hsl(h, s, l).to_nearest_color()
+    }
+
+    pub fn to_hsl(self) -> (f64, f64, f64) {
+        FloatColor::from(self).to_hsl()
+    }
+
+    /// Linear interpolation, `t = 0` gives `self` and `t = 1` gives `other`.
+    pub fn lerp(self, other: Color, t: f64) -> Self {
+        FloatColor::from(self).lerp(other.into(), t).to_nearest_color()
+    }
+
+    /// Relative luminance of the channel values in `[0, 1]`.
+    pub fn luminance(self) -> f64 {
+        FloatColor::from(self).luminance()
+    }
+}
+
+/// Accepts a hex string, with or without the `#`, or a CSS color name. Hex wins when a string is both.
+impl std::str::FromStr for Color {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Color::from_hex(s)
+            .ok()
+            .or_else(|| Color::named(s))
+            .ok_or_else(|| format!("unknown color `{s}`"))
+    }
 }
 
 impl From<[u8; 3]> for Color {
@@ -153,6 +229,374 @@ macro_rules! op_float {
 op_int!(u8, u16, u32, u64, u128, i16, i32, i64, i128);
 op_float!(f32, f64);
 
+/// Color with unbounded floating point channels, used for light intensities
+/// and HDR radiance. A channel value of `1.0` maps to `255` in a [`Color`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FloatColor {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+#[inline(always)]
+pub const fn float_color(r: f64, g: f64, b: f64) -> FloatColor {
+    FloatColor::new(r, g, b)
+}
+
+impl FloatColor {
+    pub const BLACK: FloatColor = FloatColor::splat(0.);
+
+    pub const fn new(r: f64, g: f64, b: f64) -> Self {
+        Self {
+            r, g, b
+        }
+    }
+
+    /// Same value on every channel.
+    pub const fn splat(v: f64) -> Self {
+        Self::new(v, v, v)
+    }
+
+    /// Relative luminance (Rec. 709 weights).
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Hue in degrees, saturation and value in `[0, 1]`.
+    pub fn from_hsv(h: f64, s: f64, v: f64) -> Self {
+        let c = v * s;
+        Self::from_hue(h, c, v - c)
+    }
+
+    /// Hue in degrees, saturation and value. Grays have a hue of `0`.
+    pub fn to_hsv(self) -> (f64, f64, f64) {
+        let (h, max, min) = self.hue();
+        let s = if max > 0. { (max - min) / max } else { 0. };
+        (h, s, max)
+    }
+
+    /// Hue in degrees, saturation and lightness in `[0, 1]`.
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        let c = (1. - (2. * l - 1.).abs()) * s;
+        Self::from_hue(h, c, l - c / 2.)
+    }
+
+    /// Hue in degrees, saturation and lightness. Grays have a hue of `0`.
+    pub fn to_hsl(self) -> (f64, f64, f64) {
+        let (h, max, min) = self.hue();
+        let l = (max + min) / 2.;
+        let s = if max > min { (max - min) / (1. - (2. * l - 1.).abs()) } else { 0. };
+        (h, s, l)
+    }
+
+    /// Color of the given hue and chroma, offset by `m` on every channel.
+    fn from_hue(h: f64, chroma: f64, m: f64) -> Self {
+        let h = h.rem_euclid(360.) / 60.;
+        let x = chroma * (1. - (h % 2. - 1.).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        Self::new(r + m, g + m, b + m)
+    }
+
+    /// Hue in degrees along with the largest and smallest channel.
+    fn hue(self) -> (f64, f64, f64) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let d = max - min;
+        let h = if d == 0. {
+            0.
+        } else if max == self.r {
+            ((self.g - self.b) / d).rem_euclid(6.)
+        } else if max == self.g {
+            (self.b - self.r) / d + 2.
+        } else {
+            (self.r - self.g) / d + 4.
+        };
+        (h * 60., max, min)
+    }
+
+    /// Decodes every channel with [`srgb_to_linear`].
+    pub fn srgb_to_linear(self) -> Self {
+        Self::new(srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b))
+    }
+
+    /// Encodes every channel with [`linear_to_srgb`].
+    pub fn linear_to_srgb(self) -> Self {
+        Self::new(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b))
+    }
+
+    /// Linear interpolation, `t = 0` gives `self` and `t = 1` gives `other`.
+    pub fn lerp(self, other: FloatColor, t: f64) -> Self {
+        self + (other - self) * t
+    }
+
+    /// Clamps to `[0, 1]` and scales into 8-bit channels.
+    pub fn to_color(self) -> Color {
+        let c = |v: f64| (v * 255.).clamp(0., 255.) as u8;
+        Color::new(c(self.r), c(self.g), c(self.b))
+    }
+
+    /// Like [`FloatColor::to_color`], but rounds to the nearest level instead
+    /// of down, for conversions that should survive a round trip.
+    pub(crate) fn to_nearest_color(self) -> Color {
+        let c = |v: f64| (v * 255.).round().clamp(0., 255.) as u8;
+        Color::new(c(self.r), c(self.g), c(self.b))
+    }
+}
+
+/// sRGB transfer function, from an encoded channel to linear light.
+pub fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Inverse of [`srgb_to_linear`]. Negative values are clamped to zero.
+pub fn linear_to_srgb(v: f64) -> f64 {
+    let v = v.max(0.);
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1. / 2.4) - 0.055 }
+}
+
+impl From<Color> for FloatColor {
+    fn from(c: Color) -> Self {
+        FloatColor::new(c.r as f64 / 255., c.g as f64 / 255., c.b as f64 / 255.)
+    }
+}
+
+impl From<[f32; 3]> for FloatColor {
+    fn from(a: [f32; 3]) -> Self {
+        FloatColor::new(a[0] as f64, a[1] as f64, a[2] as f64)
+    }
+}
+
+impl std::ops::Add for FloatColor {
+    type Output = FloatColor;
+    fn add(self, rhs: FloatColor) -> Self::Output {
+        FloatColor::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+impl std::ops::AddAssign for FloatColor {
+    fn add_assign(&mut self, rhs: FloatColor) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::ops::Sub for FloatColor {
+    type Output = FloatColor;
+    fn sub(self, rhs: FloatColor) -> Self::Output {
+        FloatColor::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+
+impl std::ops::Mul for FloatColor {
+    type Output = FloatColor;
+    fn mul(self, rhs: FloatColor) -> Self::Output {
+        FloatColor::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl std::ops::Mul<f64> for FloatColor {
+    type Output = FloatColor;
+    fn mul(self, rhs: f64) -> Self::Output {
+        FloatColor::new(self.r * rhs, self.g * rhs, self.b * rhs)
+    }
+}
+
+impl std::ops::MulAssign<f64> for FloatColor {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = *self * rhs;
+    }
+}
+
+impl std::ops::Div for FloatColor {
+    type Output = FloatColor;
+    fn div(self, rhs: FloatColor) -> Self::Output {
+        FloatColor::new(self.r / rhs.r, self.g / rhs.g, self.b / rhs.b)
+    }
+}
+
+impl std::ops::Div<f64> for FloatColor {
+    type Output = FloatColor;
+    fn div(self, rhs: f64) -> Self::Output {
+        FloatColor::new(self.r / rhs, self.g / rhs, self.b / rhs)
+    }
+}
+
+impl std::ops::DivAssign<f64> for FloatColor {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs;
+    }
+}
+
+/// Scales each channel of the color by the matching channel of the intensity.
+impl std::ops::Mul<FloatColor> for Color {
+    type Output = Color;
+    fn mul(self, rhs: FloatColor) -> Self::Output {
+        Color::new(
+            (self.r as f64 * rhs.r).clamp(u8::MIN as f64, u8::MAX as f64) as u8,
+            (self.g as f64 * rhs.g).clamp(u8::MIN as f64, u8::MAX as f64) as u8,
+            (self.b as f64 * rhs.b).clamp(u8::MIN as f64, u8::MAX as f64) as u8,
+        )
+    }
+}
+
+/// The CSS named colors.
+const NAMED_COLORS: &[(&str, Color)] = &[
+    ("aliceblue", color(0xf0, 0xf8, 0xff)),
+    ("antiquewhite", color(0xfa, 0xeb, 0xd7)),
+    ("aqua", color(0x00, 0xff, 0xff)),
+    ("aquamarine", color(0x7f, 0xff, 0xd4)),
+    ("azure", color(0xf0, 0xff, 0xff)),
+    ("beige", color(0xf5, 0xf5, 0xdc)),
+    ("bisque", color(0xff, 0xe4, 0xc4)),
+    ("black", color(0x00, 0x00, 0x00)),
+    ("blanchedalmond", color(0xff, 0xeb, 0xcd)),
+    ("blue", color(0x00, 0x00, 0xff)),
+    ("blueviolet", color(0x8a, 0x2b, 0xe2)),
+    ("brown", color(0xa5, 0x2a, 0x2a)),
+    ("burlywood", color(0xde, 0xb8, 0x87)),
+    ("cadetblue", color(0x5f, 0x9e, 0xa0)),
+    ("chartreuse", color(0x7f, 0xff, 0x00)),
+    ("chocolate", color(0xd2, 0x69, 0x1e)),
+    ("coral", color(0xff, 0x7f, 0x50)),
+    ("cornflowerblue", color(0x64, 0x95, 0xed)),
+    ("cornsilk", color(0xff, 0xf8, 0xdc)),
+    ("crimson", color(0xdc, 0x14, 0x3c)),
+    ("cyan", color(0x00, 0xff, 0xff)),
+    ("darkblue", color(0x00, 0x00, 0x8b)),
+    ("darkcyan", color(0x00, 0x8b, 0x8b)),
+    ("darkgoldenrod", color(0xb8, 0x86, 0x0b)),
+    ("darkgray", color(0xa9, 0xa9, 0xa9)),
+    ("darkgreen", color(0x00, 0x64, 0x00)),
+    ("darkgrey", color(0xa9, 0xa9, 0xa9)),
+    ("darkkhaki", color(0xbd, 0xb7, 0x6b)),
+    ("darkmagenta", color(0x8b, 0x00, 0x8b)),
+    ("darkolivegreen", color(0x55, 0x6b, 0x2f)),
+    ("darkorange", color(0xff, 0x8c, 0x00)),
+    ("darkorchid", color(0x99, 0x32, 0xcc)),
+    ("darkred", color(0x8b, 0x00, 0x00)),
+    ("darksalmon", color(0xe9, 0x96, 0x7a)),
+    ("darkseagreen", color(0x8f, 0xbc, 0x8f)),
+    ("darkslateblue", color(0x48, 0x3d, 0x8b)),
+    ("darkslategray", color(0x2f, 0x4f, 0x4f)),
+    ("darkslategrey", color(0x2f, 0x4f, 0x4f)),
+    ("darkturquoise", color(0x00, 0xce, 0xd1)),
+    ("darkviolet", color(0x94, 0x00, 0xd3)),
+    ("deeppink", color(0xff, 0x14, 0x93)),
+    ("deepskyblue", color(0x00, 0xbf, 0xff)),
+    ("dimgray", color(0x69, 0x69, 0x69)),
+    ("dimgrey", color(0x69, 0x69, 0x69)),
+    ("dodgerblue", color(0x1e, 0x90, 0xff)),
+    ("firebrick", color(0xb2, 0x22, 0x22)),
+    ("floralwhite", color(0xff, 0xfa, 0xf0)),
+    ("forestgreen", color(0x22, 0x8b, 0x22)),
+    ("fuchsia", color(0xff, 0x00, 0xff)),
+    ("gainsboro", color(0xdc, 0xdc, 0xdc)),
+    ("ghostwhite", color(0xf8, 0xf8, 0xff)),
+    ("gold", color(0xff, 0xd7, 0x00)),
+    ("goldenrod", color(0xda, 0xa5, 0x20)),
+    ("gray", color(0x80, 0x80, 0x80)),
+    ("green", color(0x00, 0x80, 0x00)),
+    ("greenyellow", color(0xad, 0xff, 0x2f)),
+    ("grey", color(0x80, 0x80, 0x80)),
+    ("honeydew", color(0xf0, 0xff, 0xf0)),
+    ("hotpink", color(0xff, 0x69, 0xb4)),
+    ("indianred", color(0xcd, 0x5c, 0x5c)),
+    ("indigo", color(0x4b, 0x00, 0x82)),
+    ("ivory", color(0xff, 0xff, 0xf0)),
+    ("khaki", color(0xf0, 0xe6, 0x8c)),
+    ("lavender", color(0xe6, 0xe6, 0xfa)),
+    ("lavenderblush", color(0xff, 0xf0, 0xf5)),
+    ("lawngreen", color(0x7c, 0xfc, 0x00)),
+    ("lemonchiffon", color(0xff, 0xfa, 0xcd)),
+    ("lightblue", color(0xad, 0xd8, 0xe6)),
+    ("lightcoral", color(0xf0, 0x80, 0x80)),
+    ("lightcyan", color(0xe0, 0xff, 0xff)),
+    ("lightgoldenrodyellow", color(0xfa, 0xfa, 0xd2)),
+    ("lightgray", color(0xd3, 0xd3, 0xd3)),
+    ("lightgreen", color(0x90, 0xee, 0x90)),
+    ("lightgrey", color(0xd3, 0xd3, 0xd3)),
+    ("lightpink", color(0xff, 0xb6, 0xc1)),
+    ("lightsalmon", color(0xff, 0xa0, 0x7a)),
+    ("lightseagreen", color(0x20, 0xb2, 0xaa)),
+    ("lightskyblue", color(0x87, 0xce, 0xfa)),
+    ("lightslategray", color(0x77, 0x88, 0x99)),
+    ("lightslategrey", color(0x77, 0x88, 0x99)),
+    ("lightsteelblue", color(0xb0, 0xc4, 0xde)),
+    ("lightyellow", color(0xff, 0xff, 0xe0)),
+    ("lime", color(0x00, 0xff, 0x00)),
+    ("limegreen", color(0x32, 0xcd, 0x32)),
+    ("linen", color(0xfa, 0xf0, 0xe6)),
+    ("magenta", color(0xff, 0x00, 0xff)),
+    ("maroon", color(0x80, 0x00, 0x00)),
+    ("mediumaquamarine", color(0x66, 0xcd, 0xaa)),
+    ("mediumblue", color(0x00, 0x00, 0xcd)),
+    ("mediumorchid", color(0xba, 0x55, 0xd3)),
+    ("mediumpurple", color(0x93, 0x70, 0xdb)),
+    ("mediumseagreen", color(0x3c, 0xb3, 0x71)),
+    ("mediumslateblue", color(0x7b, 0x68, 0xee)),
+    ("mediumspringgreen", color(0x00, 0xfa, 0x9a)),
+    ("mediumturquoise", color(0x48, 0xd1, 0xcc)),
+    ("mediumvioletred", color(0xc7, 0x15, 0x85)),
+    ("midnightblue", color(0x19, 0x19, 0x70)),
+    ("mintcream", color(0xf5, 0xff, 0xfa)),
+    ("mistyrose", color(0xff, 0xe4, 0xe1)),
+    ("moccasin", color(0xff, 0xe4, 0xb5)),
+    ("navajowhite", color(0xff, 0xde, 0xad)),
+    ("navy", color(0x00, 0x00, 0x80)),
+    ("oldlace", color(0xfd, 0xf5, 0xe6)),
+    ("olive", color(0x80, 0x80, 0x00)),
+    ("olivedrab", color(0x6b, 0x8e, 0x23)),
+    ("orange", color(0xff, 0xa5, 0x00)),
+    ("orangered", color(0xff, 0x45, 0x00)),
+    ("orchid", color(0xda, 0x70, 0xd6)),
+    ("palegoldenrod", color(0xee, 0xe8, 0xaa)),
+    ("palegreen", color(0x98, 0xfb, 0x98)),
+    ("paleturquoise", color(0xaf, 0xee, 0xee)),
+    ("palevioletred", color(0xdb, 0x70, 0x93)),
+    ("papayawhip", color(0xff, 0xef, 0xd5)),
+    ("peachpuff", color(0xff, 0xda, 0xb9)),
+    ("peru", color(0xcd, 0x85, 0x3f)),
+    ("pink", color(0xff, 0xc0, 0xcb)),
+    ("plum", color(0xdd, 0xa0, 0xdd)),
+    ("powderblue", color(0xb0, 0xe0, 0xe6)),
+    ("purple", color(0x80, 0x00, 0x80)),
+    ("rebeccapurple", color(0x66, 0x33, 0x99)),
+    ("red", color(0xff, 0x00, 0x00)),
+    ("rosybrown", color(0xbc, 0x8f, 0x8f)),
+    ("royalblue", color(0x41, 0x69, 0xe1)),
+    ("saddlebrown", color(0x8b, 0x45, 0x13)),
+    ("salmon", color(0xfa, 0x80, 0x72)),
+    ("sandybrown", color(0xf4, 0xa4, 0x60)),
+    ("seagreen", color(0x2e, 0x8b, 0x57)),
+    ("seashell", color(0xff, 0xf5, 0xee)),
+    ("sienna", color(0xa0, 0x52, 0x2d)),
+    ("silver", color(0xc0, 0xc0, 0xc0)),
+    ("skyblue", color(0x87, 0xce, 0xeb)),
+    ("slateblue", color(0x6a, 0x5a, 0xcd)),
+    ("slategray", color(0x70, 0x80, 0x90)),
+    ("slategrey", color(0x70, 0x80, 0x90)),
+    ("snow", color(0xff, 0xfa, 0xfa)),
+    ("springgreen", color(0x00, 0xff, 0x7f)),
+    ("steelblue", color(0x46, 0x82, 0xb4)),
+    ("tan", color(0xd2, 0xb4, 0x8c)),
+    ("teal", color(0x00, 0x80, 0x80)),
+    ("thistle", color(0xd8, 0xbf, 0xd8)),
+    ("tomato", color(0xff, 0x63, 0x47)),
+    ("turquoise", color(0x40, 0xe0, 0xd0)),
+    ("violet", color(0xee, 0x82, 0xee)),
+    ("wheat", color(0xf5, 0xde, 0xb3)),
+    ("white", color(0xff, 0xff, 0xff)),
+    ("whitesmoke", color(0xf5, 0xf5, 0xf5)),
+    ("yellow", color(0xff, 0xff, 0x00)),
+    ("yellowgreen", color(0x9a, 0xcd, 0x32)),
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,5 +609,35 @@ mod tests {
         assert_eq!(color(250, 50, 10) / 5, color(50, 10, 2));
         assert_eq!(color(100, 50, 10) * 1.5, color(150, 75, 15));
         assert_eq!(color(100, 50, 10) / 1.5, color(66, 33, 6));
+        assert_eq!(color(100, 50, 10) * float_color(1.5, 2., 0.5), color(150, 100, 5));
+        assert_eq!(float_color(0.5, 2., -1.).to_color(), color(127, 255, 0));
+        assert_eq!(FloatColor::from(color(3, 100, 251)).to_color(), color(3, 100, 251));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Color::from_hex("#ff8800"), Ok(color(255, 136, 0)));
+        assert_eq!(Color::from_hex("f80"), Ok(color(255, 136, 0)));
+        assert!(Color::from_hex("#ff88").is_err());
+        assert!(Color::from_hex("#gg8800").is_err());
+        assert_eq!(color(255, 136, 0).to_hex(), "#ff8800");
+        assert_eq!("Tomato".parse(), Ok(color(255, 99, 71)));
+        assert_eq!("ff8800".parse(), Ok(color(255, 136, 0)));
+        assert_eq!("#f80".parse(), Ok(color(255, 136, 0)));
+        assert!("tomatoes".parse::<Color>().is_err());
+
+        for c in [color(255, 99, 71), color(3, 100, 251), color(128, 128, 128), color(0, 0, 0)] {
+            let (h, s, v) = c.to_hsv();
+            assert_eq!(Color::from_hsv(h, s, v), c);
+            let (h, s, l) = c.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), c);
+            assert_eq!(Color::from_linear(c.to_linear()), c);
+        }
+        assert_eq!(Color::from_hsv(120., 1., 1.), color(0, 255, 0));
+        assert_eq!(Color::from_hsl(240., 1., 0.25), color(0, 0, 128));
+        assert!((color(128, 128, 128).to_linear().r - 0.2158).abs() < 1e-4);
+
+        assert_eq!(color(0, 100, 200).lerp(color(100, 0, 0), 0.5), color(50, 50, 100));
+        assert_eq!(color(255, 255, 255).luminance(), 1.);
     }
 }
@@ -0,0 +1,197 @@
+use image::{Luma, Rgb, Rgb32FImage};
+
+use crate::aov::Gray32FImage;
+use crate::color::FloatColor;
+
+/// Edge-aware à-trous wavelet filter (Dammertz et al., "Edge-Avoiding
+/// À-Trous Wavelet Transform for fast Global Illumination Filtering").
+///
+/// Every iteration blurs with a 5x5 kernel whose taps are twice as far apart
+/// as in the previous one, weighting neighbours down the more their color,
+/// normal, depth and albedo differ, so edges between objects stay sharp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Denoiser {
+    /// Number of passes; the filter reaches `2^(iterations + 1)` pixels.
+    pub iterations: u32,
+    /// Color difference that halves a weight in the first pass. Halved on
+    /// every following pass, as noise is already reduced.
+    pub sigma_color: f64,
+    /// Exponent of the normals' dot product.
+    pub sigma_normal: f64,
+    /// Relative depth difference tolerated per pixel of distance.
+    pub sigma_depth: f64,
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 0.6,
+            sigma_normal: 64.,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+/// Per-pixel features of the primary hits that steer the filter. Pixels
+/// whose ray missed have infinite depth.
+#[derive(Debug, Clone, Copy)]
+pub struct Guides<'a> {
+    pub normal: &'a Rgb32FImage,
+    pub depth: &'a Gray32FImage,
+    pub albedo: &'a Rgb32FImage,
+}
+
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+impl Denoiser {
+    /// Filters `image`, which must have the size of the guides. Lighting is
+    /// filtered apart from the albedo, so textures keep their detail.
+    pub fn apply(&self, image: &Rgb32FImage, guides: Guides) -> Rgb32FImage {
+        let (w, h) = image.dimensions();
+        let albedo = |x, y| {
+            let a = FloatColor::from(guides.albedo.get_pixel(x, y).0);
+            FloatColor::new(a.r.max(ALBEDO_EPSILON), a.g.max(ALBEDO_EPSILON), a.b.max(ALBEDO_EPSILON))
+        };
+        let mut current = Rgb32FImage::from_fn(w, h, |x, y| to_rgb(pixel(image, x, y) / albedo(x, y)));
+
+        let mut sigma_color = self.sigma_color;
+        for i in 0..self.iterations {
+            current = self.pass(&current, guides, 1 << i, sigma_color);
+            sigma_color /= 2.;
+        }
+        Rgb32FImage::from_fn(w, h, |x, y| to_rgb(pixel(&current, x, y) * albedo(x, y)))
+    }
+
+    fn pass(&self, image: &Rgb32FImage, guides: Guides, step: i64, sigma_color: f64) -> Rgb32FImage {
+        let (w, h) = image.dimensions();
+        Rgb32FImage::from_fn(w, h, |x, y| {
+            let color = pixel(image, x, y);
+            let normal = pixel(guides.normal, x, y);
+            let Luma([depth]) = *guides.depth.get_pixel(x, y);
+            let albedo = pixel(guides.albedo, x, y);
+
+            let mut sum = FloatColor::BLACK;
+            let mut total = 0.;
+            for (ky, ry) in KERNEL.iter().enumerate() {
+                for (kx, rx) in KERNEL.iter().enumerate() {
+                    let qx = x as i64 + (kx as i64 - 2) * step;
+                    let qy = y as i64 + (ky as i64 - 2) * step;
+                    if qx < 0 || qy < 0 || qx >= w as i64 || qy >= h as i64 {
+                        continue;
+                    }
+                    let (qx, qy) = (qx as u32, qy as u32);
+
+                    let Luma([q_depth]) = *guides.depth.get_pixel(qx, qy);
+                    let w_depth = match (depth.is_finite(), q_depth.is_finite()) {
+                        (true, true) => {
+                            let d = (depth - q_depth).abs() as f64;
+                            let distance = (((qx as f64 - x as f64).powi(2) + (qy as f64 - y as f64).powi(2)).sqrt()).max(1.);
+                            (-d / (self.sigma_depth * depth.max(f32::EPSILON) as f64 * distance)).exp()
+                        }
+                        (false, false) => 1.,
+                        _ => continue,
+                    };
+                    let q_normal = pixel(guides.normal, qx, qy);
+                    let n_dot = normal.r * q_normal.r + normal.g * q_normal.g + normal.b * q_normal.b;
+                    let w_normal = if depth.is_finite() { n_dot.max(0.).powf(self.sigma_normal) } else { 1. };
+                    let w_albedo = (-distance2(albedo, pixel(guides.albedo, qx, qy)) / (self.sigma_albedo * self.sigma_albedo)).exp();
+
+                    let q_color = pixel(image, qx, qy);
+                    let w_color = (-distance2(color, q_color) / (sigma_color * sigma_color)).exp();
+
+                    let weight = rx * ry * w_depth * w_normal * w_albedo * w_color;
+                    sum += q_color * weight;
+                    total += weight;
+                }
+            }
+            to_rgb(if total > 0. { sum / total } else { color })
+        })
+    }
+}
+
+/// Peak signal to noise ratio in decibels of `image` against `reference`,
+/// for values in `[0, 1]`.
+pub fn psnr(image: &Rgb32FImage, reference: &Rgb32FImage) -> f64 {
+    let n = image.as_raw().len().max(1) as f64;
+    let mse = image.as_raw().iter()
+        .zip(reference.as_raw())
+        .map(|(a, b)| (a.clamp(0., 1.) as f64 - b.clamp(0., 1.) as f64).powi(2))
+        .sum::<f64>() / n;
+    10. * (1. / mse.max(f64::MIN_POSITIVE)).log10()
+}
+
+/// Smallest albedo lighting is divided by, so black surfaces keep theirs.
+const ALBEDO_EPSILON: f64 = 0.01;
+
+fn pixel(image: &Rgb32FImage, x: u32, y: u32) -> FloatColor {
+    FloatColor::from(image.get_pixel(x, y).0)
+}
+
+fn to_rgb(c: FloatColor) -> Rgb<f32> {
+    Rgb([c.r as f32, c.g as f32, c.b as f32])
+}
+
+fn distance2(a: FloatColor, b: FloatColor) -> f64 {
+    let d = a - b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::color;
+    use crate::math::point;
+    use crate::{Canvas, Light, LightLinks, Material, RayTracer, Sphere};
+
+    #[test]
+    fn keeps_edges() {
+        let (w, h) = (32, 32);
+        // two objects meeting at x = 16, each lit evenly
+        let clean = Rgb32FImage::from_fn(w, h, |x, _| Rgb([if x < 16 { 0.2 } else { 0.8 }; 3]));
+        let noisy = Rgb32FImage::from_fn(w, h, |x, y| {
+            let n = ((x * 7919 + y * 104729) % 101) as f32 / 100. - 0.5;
+            let Rgb([c, ..]) = *clean.get_pixel(x, y);
+            Rgb([c + n * 0.3; 3])
+        });
+        let normal = Rgb32FImage::from_fn(w, h, |x, _| Rgb(if x < 16 { [0., 0., -1.] } else { [1., 0., 0.] }));
+        let depth = Gray32FImage::from_pixel(w, h, Luma([5.]));
+        let albedo = Rgb32FImage::from_pixel(w, h, Rgb([1., 1., 1.]));
+
+        let denoised = Denoiser::default().apply(&noisy, Guides { normal: &normal, depth: &depth, albedo: &albedo });
+        // the noise is gone, the edge isn't blurred
+        assert!((denoised.get_pixel(15, 16).0[0] - 0.2).abs() < 0.05);
+        assert!((denoised.get_pixel(16, 16).0[0] - 0.8).abs() < 0.05);
+    }
+
+    #[test]
+    fn approaches_converged_render() {
+        // an emissive ball lighting the floor and a neighbour, few samples
+        let render = |samples: u32, denoise: bool| {
+            let mut rt = RayTracer::new(Canvas::new(96, 96));
+            rt.camera.position = point(0., 0., -5.);
+            rt.sampling.samples = samples;
+            rt.sampling.emission_samples = 2;
+            rt.denoise = denoise.then(Denoiser::default);
+            let glow = Material {
+                emission: FloatColor::from(color(255, 120, 40)) * 3.,
+                ..Material::new(color(0, 0, 0))
+            };
+            rt.spheres = vec![
+                Sphere::new(point(0., -5001., 0.), 5000., Material::new(color(200, 200, 200))),
+                Sphere::new(point(0., -0.5, 3.), 0.5, glow),
+                Sphere::new(point(1.2, -0.4, 3.), 0.6, Material::new(color(100, 150, 255))),
+            ];
+            rt.lights.push(Light::Ambient { intensity: 0.05, links: LightLinks::default() });
+            image::DynamicImage::ImageRgb8(rt.render().clone()).to_rgb32f()
+        };
+        let converged = render(64, false);
+        let noisy = render(4, false);
+        let denoised = render(4, true);
+
+        let (before, after) = (psnr(&noisy, &converged), psnr(&denoised, &converged));
+        assert!(after > before + 1.5, "{before} dB before, {after} dB after");
+    }
+}
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use cgmath::prelude::*;
+use image::codecs::hdr::HdrDecoder;
+use image::Rgb32FImage;
+
+use crate::color::FloatColor;
+use crate::math::*;
+use crate::scene::LightLinks;
+
+/// Equirectangular HDR map surrounding the scene.
+///
+/// Rays that miss every object see the map, and the shading of diffuse and
+/// glossy surfaces gathers light from it by importance sampling its brightest
+/// texels.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    map: Rgb32FImage,
+    distribution: Distribution,
+    /// Multiplier applied to the map's radiance.
+    pub intensity: f64,
+    /// Rotation around the vertical axis, in degrees.
+    pub rotation: f64,
+    /// Sample directions per shading point.
+    pub samples: u32,
+    /// Whether camera rays that miss see the map instead of the background
+    /// color. Reflections see it either way.
+    pub visible: bool,
+    /// Objects the map lights.
+    pub links: LightLinks,
+}
+
+impl Environment {
+    /// Fails if the map has no texels.
+    pub fn new(map: Rgb32FImage) -> Result<Self, String> {
+        if map.width() == 0 || map.height() == 0 {
+            return Err("empty hdr image".into());
+        }
+        let distribution = Distribution::new(&map);
+        Ok(Self {
+            map,
+            distribution,
+            intensity: 1.,
+            rotation: 0.,
+            samples: 16,
+            visible: true,
+            links: LightLinks::default(),
+        })
+    }
+
+    /// Loads a Radiance `.hdr` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("could not open `{}`: {e}", path.display()))?;
+        let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+        let meta = decoder.metadata();
+
+        let data: Vec<f32> = decoder
+            .read_image_native()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .flat_map(|p| p.to_hdr().0)
+            .collect();
+
+        let map = Rgb32FImage::from_raw(meta.width, meta.height, data)
+            .ok_or("invalid hdr image size")?;
+        Self::new(map)
+    }
+
+    pub fn map(&self) -> &Rgb32FImage {
+        &self.map
+    }
+
+    /// Radiance arriving from direction `dir`, already scaled by `intensity`.
+    pub fn radiance(&self, dir: Vector) -> FloatColor {
+        let (u, v) = self.dir_to_uv(dir);
+        let (w, h) = self.map.dimensions();
+        let x = ((u * w as f64) as u32).min(w - 1);
+        let y = ((v * h as f64) as u32).min(h - 1);
+
+        FloatColor::from(self.map.get_pixel(x, y).0) * self.intensity
+    }
+
+    /// Picks a direction with probability roughly proportional to the map's
+    /// brightness. Returns the direction and its solid angle density.
+    pub fn sample(&self, u1: f64, u2: f64) -> Option<(Vector, f64)> {
+        let (u, v, pdf) = self.distribution.sample(u1, u2)?;
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0. {
+            return None;
+        }
+        let pdf = pdf / (2. * PI * PI * sin_theta);
+        Some((self.uv_to_dir(u, v), pdf))
+    }
+
+    fn dir_to_uv(&self, dir: Vector) -> (f64, f64) {
+        let d = rotate_y(dir.normalize(), -self.rotation);
+        let u = 0.5 + d.x.atan2(d.z) / (2. * PI);
+        let v = d.y.clamp(-1., 1.).acos() / PI;
+        (u, v)
+    }
+
+    fn uv_to_dir(&self, u: f64, v: f64) -> Vector {
+        let phi = (u - 0.5) * 2. * PI;
+        let theta = v * PI;
+        let d = vector(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos());
+        rotate_y(d, self.rotation)
+    }
+}
+
+fn rotate_y(v: Vector, degrees: f64) -> Vector {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    vector(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos)
+}
+
+/// Piecewise constant 2D distribution over the texels of the map, weighted by
+/// luminance and by the solid angle each row covers.
+#[derive(Debug, Clone)]
+struct Distribution {
+    width: usize,
+    height: usize,
+    weights: Vec<f64>,
+    /// `height` rows of `width + 1` entries each.
+    conditional_cdf: Vec<f64>,
+    marginal_cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution {
+    fn new(map: &Rgb32FImage) -> Self {
+        let (width, height) = (map.width() as usize, map.height() as usize);
+        let mut weights = Vec::with_capacity(width * height);
+        let mut conditional_cdf = Vec::with_capacity((width + 1) * height);
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.);
+
+        for y in 0..height {
+            let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+            let mut sum = 0.;
+            conditional_cdf.push(0.);
+            for x in 0..width {
+                let w = FloatColor::from(map.get_pixel(x as u32, y as u32).0).luminance().max(0.) * sin_theta;
+                weights.push(w);
+                sum += w;
+                conditional_cdf.push(sum);
+            }
+            marginal_cdf.push(marginal_cdf[y] + sum);
+        }
+        let total = marginal_cdf[height];
+
+        Self {
+            width, height,
+            weights,
+            conditional_cdf,
+            marginal_cdf,
+            total,
+        }
+    }
+
+    /// Returns `(u, v, pdf)`, with the density measured over the unit square.
+    fn sample(&self, u1: f64, u2: f64) -> Option<(f64, f64, f64)> {
+        if self.total <= 0. {
+            return None;
+        }
+
+        let (y, dv) = sample_cdf(&self.marginal_cdf, u2);
+        let row = &self.conditional_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, du) = sample_cdf(row, u1);
+
+        let pdf = self.weights[y * self.width + x] / self.total * (self.width * self.height) as f64;
+        let u = (x as f64 + du) / self.width as f64;
+        let v = (y as f64 + dv) / self.height as f64;
+        Some((u, v, pdf))
+    }
+}
+
+/// Finds the interval of an unnormalized cdf that `u` falls in, and the
+/// offset inside that interval.
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let target = u * cdf[cdf.len() - 1];
+    let i = cdf
+        .partition_point(|&c| c <= target)
+        .clamp(1, cdf.len() - 1) - 1;
+
+    let width = cdf[i + 1] - cdf[i];
+    let offset = if width > 0. { (target - cdf[i]) / width } else { 0.5 };
+    (i, offset.clamp(0., 1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uv_roundtrip() {
+        let mut env = Environment::new(Rgb32FImage::new(8, 4)).unwrap();
+        env.rotation = 30.;
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.75)] {
+            let (u2, v2) = env.dir_to_uv(env.uv_to_dir(u, v));
+            assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn samples_bright_texel() {
+        let mut map = Rgb32FImage::new(8, 4);
+        map.put_pixel(5, 2, image::Rgb([10., 10., 10.]));
+        let env = Environment::new(map).unwrap();
+
+        for (u1, u2) in [(0., 0.), (0.5, 0.5), (0.99, 0.99)] {
+            let (dir, pdf) = env.sample(u1, u2).unwrap();
+            let (u, v) = env.dir_to_uv(dir);
+            assert_eq!(((u * 8.) as u32, (v * 4.) as u32), (5, 2));
+            assert!(pdf > 0.);
+        }
+        assert!(Environment::new(Rgb32FImage::new(8, 4)).unwrap().sample(0.5, 0.5).is_none());
+        assert_eq!(Environment::new(Rgb32FImage::new(0, 4)).unwrap_err(), "empty hdr image");
+    }
+}
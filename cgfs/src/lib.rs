@@ -1,9 +1,21 @@
 pub mod color;
 pub mod canvas;
 pub mod scene;
+pub mod material;
+pub mod texture;
 pub mod camera;
 pub mod raytracer;
 pub mod rasterizer;
+pub mod environment;
+pub mod aov;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod transform;
+pub mod bounds;
+pub mod post;
+pub mod denoise;
+pub mod quantize;
 
 #[cfg(feature = "scene")]
 pub mod toml;
@@ -18,9 +30,22 @@ pub mod math {
     pub type Vector = Vector3<f64>;
 }
 
-pub use color::{Color, color};
+pub use color::{Color, color, FloatColor, float_color};
 pub use canvas::Canvas;
-pub use scene::{Light, Sphere};
+pub use rasterizer::{draw_line, LineAlgorithm, LineStyle};
+pub use scene::{Light, LightLinks, Sphere, Group, Instance, Visibility};
+pub use material::{Material, ShadingModel};
+pub use texture::Texture;
+pub use transform::Transform;
+pub use bounds::Aabb;
 pub use camera::{Camera, Viewport};
+pub use environment::Environment;
+pub use aov::{Aov, AovBuffers};
 pub use raytracer::RayTracer;
+pub use ray::{Ray, RayKind, Hit};
+pub use render::{Progressive, Tile, Tiling, RenderContext, RenderStatus, CancelToken, Progress, RenderStats, StatsCollector, Sampling, Adaptive};
+pub use post::{PostProcess, PostStage};
+pub use denoise::Denoiser;
+pub use quantize::{Quantizer, Dither, Palette};
+pub use sampler::{Sampler, IndependentSampler, StratifiedSampler, HaltonSampler, SobolSampler};
 pub use math::*;
\ No newline at end of file
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use cgmath::prelude::*;
+
+use crate::color::{Color, FloatColor};
+use crate::math::*;
+use crate::texture::Texture;
+
+/// Surface properties shared by any number of objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub color: Color,
+    /// Phong exponent of the highlight, `0` for none.
+    pub specular: f64,
+    /// Fraction of the color that comes from the mirror reflection.
+    pub reflective: f64,
+    pub model: ShadingModel,
+    /// How metal-like a [`ShadingModel::Pbr`] surface is, from `0` to `1`.
+    pub metallic: f64,
+    /// Microfacet roughness from `0` (mirror) to `1`. For
+    /// [`ShadingModel::Pbr`] it shapes both the highlight and reflections,
+    /// for the other models it only blurs the reflection. Defaults to `0`
+    /// for every model.
+    pub roughness: f64,
+    /// Reflection rays averaged by rough surfaces hit by camera rays. Deeper
+    /// bounces take a single sample, which pixel supersampling averages.
+    pub reflection_samples: u32,
+    /// Multiplies `color`.
+    pub color_texture: Option<Arc<Texture>>,
+    /// Red channel multiplies `metallic`.
+    pub metallic_texture: Option<Arc<Texture>>,
+    /// Red channel multiplies `roughness`.
+    pub roughness_texture: Option<Arc<Texture>>,
+    /// Light given off by the surface, independent of any light shining on
+    /// it. Can go above `1` for bright sources.
+    pub emission: FloatColor,
+}
+
+/// How light from the scene's lights is turned into diffuse and specular
+/// intensity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// Diffuse only; `specular` is ignored.
+    Lambert,
+    /// Highlight from the angle between the view and the reflected light.
+    #[default]
+    Phong,
+    /// Highlight from the angle between the normal and the half vector of
+    /// the view and the light. Highlights are wider than Phong's for the same
+    /// exponent, about four times the exponent gives a similar size.
+    BlinnPhong,
+    /// Cel shading: diffuse light is quantized to `bands` steps and the
+    /// highlight is either on or off.
+    Toon { bands: u32 },
+    /// Metallic/roughness microfacet model: a GGX specular lobe with
+    /// Schlick's Fresnel and glossy reflections. Only the ray tracer shades
+    /// it fully, [`ShadingModel::shade`] treats it as Lambert.
+    Pbr,
+}
+
+impl ShadingModel {
+    /// Diffuse and specular factors for the normal `n`, the direction to the
+    /// light `l` and the direction to the viewer `v`, none of which need to
+    /// be normalized.
+    pub fn shade(&self, n: Vector, l: Vector, v: Vector, specular: f64) -> (f64, f64) {
+        let n_dot_l = n.dot(l);
+        let mut diffuse = 0.;
+        if n_dot_l > 0. {
+            diffuse = self.quantize(n_dot_l / (n.magnitude() * l.magnitude()));
+        }
+        if specular <= 0. {
+            return (diffuse, 0.);
+        }
+
+        let highlight = match self {
+            ShadingModel::Lambert | ShadingModel::Pbr => return (diffuse, 0.),
+            ShadingModel::BlinnPhong => {
+                let h = l.normalize() + v.normalize();
+                let n_dot_h = n.dot(h);
+                if n_dot_l > 0. && n_dot_h > 0. {
+                    (n_dot_h / (n.magnitude() * h.magnitude())).powf(specular)
+                } else {
+                    0.
+                }
+            }
+            ShadingModel::Phong | ShadingModel::Toon { .. } => {
+                let r = n * n.dot(l) * 2. - l;
+                let r_dot_v = r.dot(v);
+                if r_dot_v > 0. {
+                    (r_dot_v / (r.magnitude() * v.magnitude())).powf(specular)
+                } else {
+                    0.
+                }
+            }
+        };
+        match self {
+            ShadingModel::Toon { .. } => (diffuse, if highlight > 0.5 { 1. } else { 0. }),
+            _ => (diffuse, highlight),
+        }
+    }
+
+    /// Whether the model has a specular highlight at all.
+    pub fn has_specular(&self) -> bool {
+        !matches!(self, ShadingModel::Lambert | ShadingModel::Pbr)
+    }
+
+    fn quantize(&self, diffuse: f64) -> f64 {
+        match *self {
+            ShadingModel::Toon { bands } if bands > 0 => (diffuse * bands as f64).ceil() / bands as f64,
+            _ => diffuse,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(color: Color) -> Self {
+        Self {
+            color,
+            specular: 0.,
+            reflective: 0.,
+            model: ShadingModel::default(),
+            metallic: 0.,
+            roughness: 0.,
+            reflection_samples: 8,
+            color_texture: None,
+            metallic_texture: None,
+            roughness_texture: None,
+            emission: FloatColor::BLACK,
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission != FloatColor::BLACK
+    }
+
+    /// `color` at texture coordinates `uv`, including its texture.
+    pub fn color_at(&self, uv: (f64, f64)) -> FloatColor {
+        let color = FloatColor::from(self.color);
+        match &self.color_texture {
+            Some(tex) => color * tex.sample(uv),
+            None => color,
+        }
+    }
+
+    pub fn metallic_at(&self, uv: (f64, f64)) -> f64 {
+        match &self.metallic_texture {
+            Some(tex) => self.metallic * tex.sample(uv).r,
+            None => self.metallic,
+        }
+    }
+
+    pub fn roughness_at(&self, uv: (f64, f64)) -> f64 {
+        match &self.roughness_texture {
+            Some(tex) => self.roughness * tex.sample(uv).r,
+            None => self.roughness,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(Color::new(255, 255, 255))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shading_models() {
+        let n = vector(0., 1., 0.);
+        let l = vector(1., 1., 0.);
+        let v = vector(-1., 1., 0.);
+
+        let (d, s) = ShadingModel::Phong.shade(n, l, v, 10.);
+        assert!((d - 0.5f64.sqrt()).abs() < 1e-12 && (s - 1.).abs() < 1e-12);
+        assert_eq!(ShadingModel::Lambert.shade(n, l, v, 10.).1, 0.);
+
+        // the half vector is the normal
+        let (_, s) = ShadingModel::BlinnPhong.shade(n, l, v, 10.);
+        assert!((s - 1.).abs() < 1e-12);
+
+        let (d, s) = ShadingModel::Toon { bands: 4 }.shade(n, l, vector(1., 0., 0.), 10.);
+        assert_eq!((d, s), (0.75, 0.));
+    }
+}
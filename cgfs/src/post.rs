@@ -0,0 +1,192 @@
+use image::{Rgb, Rgb32FImage};
+
+use crate::color::{linear_to_srgb, FloatColor};
+
+/// Image operation applied to the rendered pixel values before they are
+/// quantized into the canvas. Values are linear unless a stage encodes them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostStage {
+    /// Multiplies by `2^stops`.
+    Exposure { stops: f64 },
+    /// Raises to `1 / gamma`.
+    Gamma { gamma: f64 },
+    /// Encodes linear values with the sRGB transfer curve.
+    Srgb,
+    /// Scales the distance from mid gray.
+    Contrast { amount: f64 },
+    /// Scales the distance from the pixel's luminance, `0` for grayscale.
+    Saturation { amount: f64 },
+    /// Darkens towards the corners, which are scaled by `1 - strength`.
+    Vignette { strength: f64 },
+    /// Blurs the part of every pixel above `threshold` luminance by `radius`
+    /// pixels and adds it back scaled by `strength`.
+    Bloom { threshold: f64, strength: f64, radius: u32 },
+    /// Unsharp mask: adds `amount` times the difference to a blurred copy.
+    Sharpen { amount: f64 },
+    /// Shifts red outwards and blue inwards, by `pixels` at the corners.
+    ChromaticAberration { pixels: f64 },
+}
+
+/// Stages applied in order by [`RayTracer::render`](crate::RayTracer::render)
+/// once every pixel is done. Progressive callbacks see the unprocessed image.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostProcess {
+    pub stages: Vec<PostStage>,
+}
+
+impl PostProcess {
+    pub fn new(stages: Vec<PostStage>) -> Self {
+        Self { stages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn apply(&self, image: &mut Rgb32FImage) {
+        for stage in &self.stages {
+            stage.apply(image);
+        }
+    }
+
+    /// Whether any stage needs values above `1`, which the renderer
+    /// otherwise clamps at every bounce.
+    pub fn needs_hdr(&self) -> bool {
+        self.stages.iter().any(PostStage::needs_hdr)
+    }
+}
+
+impl PostStage {
+    /// Whether the result depends on how far values exceed `1`. Gamma, sRGB
+    /// and chromatic aberration keep anything at or above `1` there.
+    pub fn needs_hdr(&self) -> bool {
+        !matches!(self, PostStage::Gamma { .. } | PostStage::Srgb | PostStage::ChromaticAberration { .. })
+    }
+
+    pub fn apply(&self, image: &mut Rgb32FImage) {
+        match *self {
+            PostStage::Exposure { stops } => {
+                let scale = 2f64.powf(stops);
+                map_pixels(image, |c, _, _| c * scale);
+            }
+            PostStage::Gamma { gamma } => {
+                let inv = 1. / gamma;
+                map_channels(image, |v| v.max(0.).powf(inv));
+            }
+            PostStage::Srgb => map_channels(image, linear_to_srgb),
+            PostStage::Contrast { amount } => map_channels(image, |v| (v - 0.5) * amount + 0.5),
+            PostStage::Saturation { amount } => map_pixels(image, |c, _, _| {
+                let l = c.luminance();
+                FloatColor::splat(l) + (c - FloatColor::splat(l)) * amount
+            }),
+            PostStage::Vignette { strength } => {
+                let (w, h) = image.dimensions();
+                let (cx, cy) = (w as f64 / 2., h as f64 / 2.);
+                let max = (cx * cx + cy * cy).max(f64::EPSILON);
+                map_pixels(image, |c, x, y| {
+                    let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                    c * (1. - strength * (dx * dx + dy * dy) / max).max(0.)
+                });
+            }
+            PostStage::Bloom { threshold, strength, radius } => {
+                let mut bright = image.clone();
+                map_pixels(&mut bright, |c, _, _| {
+                    let l = c.luminance();
+                    if l > threshold { c * ((l - threshold) / l) } else { FloatColor::BLACK }
+                });
+                let bright = blur(&bright, radius);
+                map_pixels(image, |c, x, y| c + pixel(&bright, x, y) * strength);
+            }
+            PostStage::Sharpen { amount } => {
+                let blurred = blur(image, 1);
+                map_pixels(image, |c, x, y| c + (c - pixel(&blurred, x, y)) * amount);
+            }
+            PostStage::ChromaticAberration { pixels } => {
+                let source = image.clone();
+                let (w, h) = image.dimensions();
+                let (cx, cy) = (w as f64 / 2., h as f64 / 2.);
+                let max = (cx * cx + cy * cy).sqrt().max(f64::EPSILON);
+                let at = |x: f64, y: f64| {
+                    let x = (x.round().max(0.) as u32).min(w - 1);
+                    let y = (y.round().max(0.) as u32).min(h - 1);
+                    pixel(&source, x, y)
+                };
+                map_pixels(image, |c, x, y| {
+                    // offsets grow linearly from the center, so the sample
+                    // for the pixel at distance `d` is taken along the same line
+                    let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                    let k = pixels / max;
+                    let red = at(x as f64 - dx * k, y as f64 - dy * k);
+                    let blue = at(x as f64 + dx * k, y as f64 + dy * k);
+                    FloatColor::new(red.r, c.g, blue.b)
+                });
+            }
+        }
+    }
+}
+
+fn pixel(image: &Rgb32FImage, x: u32, y: u32) -> FloatColor {
+    FloatColor::from(image.get_pixel(x, y).0)
+}
+
+fn map_pixels<F: Fn(FloatColor, u32, u32) -> FloatColor>(image: &mut Rgb32FImage, f: F) {
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        let c = f(FloatColor::from(p.0), x, y);
+        *p = Rgb([c.r as f32, c.g as f32, c.b as f32]);
+    }
+}
+
+fn map_channels<F: Fn(f64) -> f64>(image: &mut Rgb32FImage, f: F) {
+    for v in image.iter_mut() {
+        *v = f(*v as f64) as f32;
+    }
+}
+
+/// Separable Gaussian blur reaching `radius` pixels, clamped at the edges.
+pub(crate) fn blur(image: &Rgb32FImage, radius: u32) -> Rgb32FImage {
+    if radius == 0 {
+        return image.clone();
+    }
+    let sigma = radius as f64 / 2.;
+    let r = radius as i64;
+    let weights: Vec<f64> = (-r..=r).map(|i| (-(i * i) as f64 / (2. * sigma * sigma)).exp()).collect();
+    let total: f64 = weights.iter().sum();
+
+    let (w, h) = image.dimensions();
+    let pass = |src: &Rgb32FImage, dx: i64, dy: i64| {
+        Rgb32FImage::from_fn(w, h, |x, y| {
+            let mut sum = FloatColor::BLACK;
+            for (i, weight) in (-r..=r).zip(&weights) {
+                let sx = (x as i64 + i * dx).clamp(0, w as i64 - 1) as u32;
+                let sy = (y as i64 + i * dy).clamp(0, h as i64 - 1) as u32;
+                sum += pixel(src, sx, sy) * *weight;
+            }
+            let c = sum / total;
+            Rgb([c.r as f32, c.g as f32, c.b as f32])
+        })
+    };
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages() {
+        let mut image = Rgb32FImage::from_pixel(3, 3, Rgb([0.25, 0.25, 0.25]));
+        PostProcess::new(vec![
+            PostStage::Exposure { stops: 1. },
+            PostStage::Contrast { amount: 2. },
+        ]).apply(&mut image);
+        assert_eq!(image.get_pixel(1, 1).0, [0.5; 3]);
+
+        PostStage::Vignette { strength: 1. }.apply(&mut image);
+        assert!(image.get_pixel(0, 0).0[0] < image.get_pixel(1, 1).0[0]);
+
+        // a flat image stays flat
+        let mut flat = Rgb32FImage::from_pixel(4, 4, Rgb([0.5, 0.2, 0.1]));
+        PostStage::Sharpen { amount: 1. }.apply(&mut flat);
+        assert!((flat.get_pixel(2, 1).0[1] - 0.2).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::OnceLock;
+
+use image::{Rgb, Rgb32FImage, RgbImage};
+
+use crate::color::{Color, FloatColor};
+use crate::sampler::{hash, to_unit};
+
+/// Noise added before rounding to 8-bit channels or snapping to a palette,
+/// which trades banding in smooth gradients for fine grain.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Dither {
+    #[default]
+    None,
+    /// Ordered dithering with a `size` × `size` Bayer matrix, `size` being a
+    /// power of two.
+    Bayer { size: u32 },
+    /// Ordered dithering with a tiled blue noise mask, grain without the
+    /// Bayer crosshatch.
+    BlueNoise,
+    /// Error diffusion, pushes the rounding error of every pixel onto its
+    /// unvisited neighbours.
+    FloydSteinberg,
+}
+
+/// Fixed set of colors the image is reduced to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Color>) -> Self {
+        Self { colors }
+    }
+
+    /// The 16 colors of the CGA/EGA text modes.
+    pub fn vga() -> Self {
+        let colors = [
+            0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+            0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+        ];
+        Self::new(colors.iter().map(|&c: &u32| Color::new((c >> 16) as u8, (c >> 8) as u8, c as u8)).collect())
+    }
+
+    /// Every combination of `levels` evenly spaced values per channel,
+    /// `levels = 6` gives the 216 web safe colors.
+    pub fn uniform(levels: u32) -> Self {
+        let levels = levels.clamp(2, 256);
+        let value = |i: u32| (i * 255 / (levels - 1)) as u8;
+        let mut colors = Vec::with_capacity(levels.pow(3) as usize);
+        for r in 0..levels {
+            for g in 0..levels {
+                for b in 0..levels {
+                    colors.push(Color::new(value(r), value(g), value(b)));
+                }
+            }
+        }
+        Self::new(colors)
+    }
+
+    /// Closest color by euclidean distance, black if the palette is empty.
+    pub fn nearest(&self, c: FloatColor) -> Color {
+        let distance = |p: &Color| {
+            let p = FloatColor::from(*p) - c;
+            p.r * p.r + p.g * p.g + p.b * p.b
+        };
+        self.colors.iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .copied()
+            .unwrap_or(Color::new(0, 0, 0))
+    }
+
+    /// Typical distance between neighbouring colors, which sets how strong
+    /// ordered dithering has to be.
+    fn spacing(&self) -> f64 {
+        1. / (self.colors.len().max(2) as f64).cbrt()
+    }
+}
+
+/// Converts rendered values into 8-bit channels. The default truncates every
+/// channel like [`FloatColor::to_color`]. Dithering rounds to the nearest
+/// level once its noise is added, so on average it keeps the exact value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quantizer {
+    pub dither: Dither,
+    pub palette: Option<Palette>,
+}
+
+impl Quantizer {
+    /// Whether this is plain truncation.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, image: &Rgb32FImage) -> RgbImage {
+        let (w, h) = image.dimensions();
+        let pixel = |x, y| FloatColor::from(image.get_pixel(x, y).0);
+        let to_rgb = |c: Color| Rgb([c.r, c.g, c.b]);
+        let spacing = self.palette.as_ref().map_or(1. / 255., Palette::spacing);
+
+        match self.dither {
+            Dither::None if self.palette.is_none() => RgbImage::from_fn(w, h, |x, y| to_rgb(pixel(x, y).to_color())),
+            Dither::None => RgbImage::from_fn(w, h, |x, y| to_rgb(self.snap(pixel(x, y)))),
+            Dither::Bayer { size } => {
+                let size = size.clamp(1, 256).next_power_of_two();
+                RgbImage::from_fn(w, h, |x, y| {
+                    let offset = bayer_threshold(x % size, y % size, size) - 0.5;
+                    to_rgb(self.snap(pixel(x, y) + FloatColor::splat(offset * spacing)))
+                })
+            }
+            Dither::BlueNoise => {
+                let noise = blue_noise();
+                RgbImage::from_fn(w, h, |x, y| {
+                    let i = (y as usize % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x as usize % BLUE_NOISE_SIZE;
+                    let offset = noise[i] - 0.5;
+                    to_rgb(self.snap(pixel(x, y) + FloatColor::splat(offset * spacing)))
+                })
+            }
+            Dither::FloydSteinberg => {
+                let mut error = vec![FloatColor::BLACK; (w * h) as usize];
+                let mut out = RgbImage::new(w, h);
+                for y in 0..h {
+                    for x in 0..w {
+                        let i = (y * w + x) as usize;
+                        let wanted = clamp(pixel(x, y) + error[i]);
+                        let c = self.snap(wanted);
+                        out.put_pixel(x, y, to_rgb(c));
+
+                        let e = wanted - FloatColor::from(c);
+                        let mut spread = |dx: i64, dy: u32, weight: f64| {
+                            let nx = x as i64 + dx;
+                            if nx >= 0 && nx < w as i64 && y + dy < h {
+                                error[((y + dy) * w) as usize + nx as usize] += e * weight;
+                            }
+                        };
+                        spread(1, 0, 7. / 16.);
+                        spread(-1, 1, 3. / 16.);
+                        spread(0, 1, 5. / 16.);
+                        spread(1, 1, 1. / 16.);
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    fn snap(&self, c: FloatColor) -> Color {
+        match &self.palette {
+            Some(palette) => palette.nearest(c),
+            None => c.to_nearest_color(),
+        }
+    }
+}
+
+fn clamp(c: FloatColor) -> FloatColor {
+    FloatColor::new(c.r.clamp(0., 1.), c.g.clamp(0., 1.), c.b.clamp(0., 1.))
+}
+
+/// Threshold in `(0, 1)` of `(x, y)` in a Bayer matrix of the given size.
+fn bayer_threshold(x: u32, y: u32, size: u32) -> f64 {
+    fn index(x: u32, y: u32, size: u32) -> u32 {
+        if size <= 1 {
+            return 0;
+        }
+        let half = size / 2;
+        let quadrant = match (x / half, y / half) {
+            (0, 0) => 0,
+            (1, 0) => 2,
+            (0, _) => 3,
+            _ => 1,
+        };
+        4 * index(x % half, y % half, half) + quadrant
+    }
+    (index(x, y, size) as f64 + 0.5) / (size * size) as f64
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Tileable blue noise thresholds in `(0, 1)`, generated once with the
+/// void-and-cluster method.
+fn blue_noise() -> &'static [f64] {
+    static NOISE: OnceLock<Vec<f64>> = OnceLock::new();
+    NOISE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<f64> {
+    let n = size * size;
+    // gaussian falloff by wrapped offset
+    let falloff: Vec<f64> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f64;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+
+    struct Pattern<'a> {
+        size: usize,
+        falloff: &'a [f64],
+        set: Vec<bool>,
+        energy: Vec<f64>,
+    }
+    impl Pattern<'_> {
+        fn toggle(&mut self, p: usize) {
+            self.set[p] = !self.set[p];
+            let sign = if self.set[p] { 1. } else { -1. };
+            let (px, py) = (p % self.size, p / self.size);
+            for (q, e) in self.energy.iter_mut().enumerate() {
+                let dx = (q % self.size + self.size - px) % self.size;
+                let dy = (q / self.size + self.size - py) % self.size;
+                *e += sign * self.falloff[dy * self.size + dx];
+            }
+        }
+        /// Set point with the most energy around it.
+        fn tightest_cluster(&self) -> usize {
+            self.extreme(true, |a, b| a > b)
+        }
+        /// Empty point with the least energy around it.
+        fn largest_void(&self) -> usize {
+            self.extreme(false, |a, b| a < b)
+        }
+        fn extreme(&self, set: bool, better: fn(f64, f64) -> bool) -> usize {
+            (0..self.energy.len())
+                .filter(|&p| self.set[p] == set)
+                .reduce(|best, p| if better(self.energy[p], self.energy[best]) { p } else { best })
+                .unwrap()
+        }
+    }
+
+    // random initial pattern, relaxed until it's evenly spread
+    let mut initial = Pattern { size, falloff: &falloff, set: vec![false; n], energy: vec![0.; n] };
+    let ones = n / 10;
+    let mut placed = 0;
+    for i in 0.. {
+        if placed == ones {
+            break;
+        }
+        let p = (to_unit(hash(&[i])) * n as f64) as usize;
+        if !initial.set[p] {
+            initial.toggle(p);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = initial.tightest_cluster();
+        initial.toggle(cluster);
+        let void = initial.largest_void();
+        initial.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    // rank the initial points by removing the tightest clusters first
+    let mut pattern = Pattern { size, falloff: &falloff, set: initial.set.clone(), energy: initial.energy.clone() };
+    for r in (0..ones).rev() {
+        let p = pattern.tightest_cluster();
+        pattern.toggle(p);
+        rank[p] = r;
+    }
+    // then fill the largest voids
+    for r in ones..n {
+        let p = initial.largest_void();
+        initial.toggle(p);
+        rank[p] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let bayer: Vec<f64> = (0..4).map(|i| bayer_threshold(i % 2, i / 2, 2) * 4. - 0.5).collect();
+        assert_eq!(bayer, [0., 2., 3., 1.]);
+
+        let mut noise = blue_noise().to_vec();
+        noise.sort_by(f64::total_cmp);
+        assert!(noise.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(noise.len(), BLUE_NOISE_SIZE * BLUE_NOISE_SIZE);
+    }
+
+    #[test]
+    fn dithering_keeps_average() {
+        // a flat value halfway between two palette colors
+        let image = Rgb32FImage::from_pixel(16, 16, Rgb([0.5, 0.5, 0.5]));
+        let palette = Palette::new(vec![Color::new(0, 0, 0), Color::new(255, 255, 255)]);
+        for dither in [Dither::Bayer { size: 4 }, Dither::BlueNoise, Dither::FloydSteinberg] {
+            let out = Quantizer { dither, palette: Some(palette.clone()) }.apply(&image);
+            let white = out.pixels().filter(|p| p.0 == [255; 3]).count();
+            assert!((112..=144).contains(&white), "{dither:?}: {white}");
+        }
+        let plain = Quantizer { dither: Dither::None, palette: Some(palette) }.apply(&image);
+        assert!(plain.pixels().all(|p| p.0 == plain.get_pixel(0, 0).0));
+
+        assert_eq!(Palette::vga().nearest(FloatColor::new(0.7, 0.3, 0.)), Color::new(0xaa, 0x55, 0));
+        assert_eq!(Palette::uniform(6).colors.len(), 216);
+    }
+
+    #[test]
+    fn dithering_breaks_up_bands() {
+        // a dim gradient spanning only a few 8-bit steps
+        let image = Rgb32FImage::from_fn(64, 16, |x, _| Rgb([x as f32 / 16. / 255.; 3]));
+        let sum = |image: &RgbImage| image.pixels().map(|p| p[0] as f64).sum::<f64>();
+        let exact = image.pixels().map(|p| p[0] as f64 * 255.).sum::<f64>();
+        let plain = Quantizer::default().apply(&image);
+        // truncation loses half a level on average
+        assert!(exact - sum(&plain) > 400., "{} vs {exact}", sum(&plain));
+
+        for dither in [Dither::Bayer { size: 4 }, Dither::BlueNoise, Dither::FloydSteinberg] {
+            let out = Quantizer { dither, palette: None }.apply(&image);
+            let changed = out.pixels().zip(plain.pixels()).filter(|(a, b)| a != b).count();
+            assert!(changed > 200, "{dither:?} changed {changed} pixels");
+            assert!((sum(&out) - exact).abs() < 64., "{dither:?}: {} vs {exact}", sum(&out));
+        }
+    }
+}
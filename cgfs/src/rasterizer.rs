@@ -0,0 +1,269 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+
+/// How [`draw_line`] picks the pixels of a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineAlgorithm {
+    /// Interpolates the minor coordinate along the major axis, like in
+    /// the book.
+    Interpolation,
+    /// Integer only error accumulation.
+    #[default]
+    Bresenham,
+    /// Xiaolin Wu's anti-aliased lines, blending by pixel coverage. The only
+    /// one that keeps sub-pixel endpoints and fractional thickness.
+    Wu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    pub algorithm: LineAlgorithm,
+    /// Width in pixels, measured along the minor axis of the line. Rounded
+    /// for the aliased algorithms.
+    pub thickness: f64,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            algorithm: LineAlgorithm::default(),
+            thickness: 1.,
+        }
+    }
+}
+
+/// Values of a quantity going from `d0` at `i0` to `d1` at `i1`, one for
+/// every integer from `i0` to `i1`.
+pub fn interpolate(i0: i32, d0: f64, i1: i32, d1: f64) -> Vec<f64> {
+    if i0 == i1 {
+        return vec![d0];
+    }
+    let span = i1 as i64 - i0 as i64;
+    let a = (d1 - d0) / span as f64;
+    (0..=span).map(|i| d0 + a * i as f64).collect()
+}
+
+/// Draws a line between two points in the centered coordinates of
+/// [`Canvas::put_pixel`], clipped to the canvas. Pixel centers sit on
+/// integer coordinates.
+pub fn draw_line(canvas: &mut Canvas, p0: (f64, f64), p1: (f64, f64), color: Color, style: &LineStyle) {
+    let round = |(x, y): (f64, f64)| (x.round() as i64, y.round() as i64);
+    let thickness = style.thickness.round().max(1.) as i64;
+    match style.algorithm {
+        LineAlgorithm::Interpolation => interpolated_line(canvas, round(p0), round(p1), color, thickness),
+        LineAlgorithm::Bresenham => bresenham_line(canvas, round(p0), round(p1), color, thickness),
+        LineAlgorithm::Wu => wu_line(canvas, p0, p1, color, style.thickness),
+    }
+}
+
+/// Endpoints of an aliased line along its major axis: `u` is the major
+/// coordinate and `v` the minor one, with `u0 <= u1`.
+struct Axes {
+    steep: bool,
+    u0: i64,
+    v0: i64,
+    u1: i64,
+    v1: i64,
+}
+
+impl Axes {
+    fn new((x0, y0): (i64, i64), (x1, y1): (i64, i64)) -> Self {
+        let steep = (y1 as i128 - y0 as i128).abs() > (x1 as i128 - x0 as i128).abs();
+        let (u0, v0, u1, v1) = if steep { (y0, x0, y1, x1) } else { (x0, y0, x1, y1) };
+        if u0 <= u1 {
+            Self { steep, u0, v0, u1, v1 }
+        } else {
+            Self { steep, u0: u1, v0: v1, u1: u0, v1: v0 }
+        }
+    }
+
+    /// Part of `u0..=u1` that lies on the canvas, nothing past its edges
+    /// needs stepping through.
+    fn clipped(&self, canvas: &Canvas) -> Option<(i32, i32)> {
+        let (min, max) = if self.steep { (canvas.min_y(), canvas.max_y()) } else { (canvas.min_x(), canvas.max_x()) };
+        let first = self.u0.max(min as i64);
+        let last = self.u1.min(max as i64 - 1);
+        (first <= last).then_some((first as i32, last as i32))
+    }
+
+    /// Plots `thickness` pixels across the major axis, centered on `(u, v)`.
+    fn plot_span(&self, canvas: &mut Canvas, u: i32, v: i64, color: Color, thickness: i64) {
+        let first = v - (thickness - 1) / 2;
+        for v in first..first + thickness {
+            let Ok(v) = i32::try_from(v) else { continue };
+            let (x, y) = if self.steep { (v, u) } else { (u, v) };
+            canvas.put_pixel(x, y, color);
+        }
+    }
+}
+
+fn interpolated_line(canvas: &mut Canvas, p0: (i64, i64), p1: (i64, i64), color: Color, thickness: i64) {
+    let axes = Axes::new(p0, p1);
+    let Some((first, last)) = axes.clipped(canvas) else { return };
+    let v_at = |u: i32| {
+        let du = (axes.u1 - axes.u0) as f64;
+        let t = if du == 0. { 0. } else { (u as i64 - axes.u0) as f64 / du };
+        axes.v0 as f64 + (axes.v1 - axes.v0) as f64 * t
+    };
+
+    let vs = interpolate(first, v_at(first), last, v_at(last));
+    for (u, v) in (first..=last).zip(vs) {
+        axes.plot_span(canvas, u, v.round() as i64, color, thickness);
+    }
+}
+
+fn bresenham_line(canvas: &mut Canvas, p0: (i64, i64), p1: (i64, i64), color: Color, thickness: i64) {
+    let axes = Axes::new(p0, p1);
+    let Some((first, last)) = axes.clipped(canvas) else { return };
+    let du = (axes.u1 - axes.u0) as i128;
+    let dv = (axes.v1 as i128 - axes.v0 as i128).abs();
+    let sv = (axes.v1 as i128 - axes.v0 as i128).signum();
+    if du == 0 {
+        return axes.plot_span(canvas, first, axes.v0, color, thickness);
+    }
+
+    // v is rounded to the nearest pixel, jump straight to the first step on
+    // the canvas and carry the remainder as the error term
+    let k = (first as i64 - axes.u0) as i128;
+    let numerator = 2 * dv * k + du;
+    let mut v = axes.v0 as i128 + sv * (numerator / (2 * du));
+    let mut error = numerator % (2 * du);
+    for u in first..=last {
+        axes.plot_span(canvas, u, v.clamp(i64::MIN as i128, i64::MAX as i128) as i64, color, thickness);
+        error += 2 * dv;
+        if error >= 2 * du {
+            error -= 2 * du;
+            v += sv;
+        }
+    }
+}
+
+fn wu_line(canvas: &mut Canvas, (x0, y0): (f64, f64), (x1, y1): (f64, f64), color: Color, thickness: f64) {
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    let (mut u0, mut v0, mut u1, mut v1) = if steep { (y0, x0, y1, x1) } else { (x0, y0, x1, y1) };
+    if u0 > u1 {
+        (u0, v0, u1, v1) = (u1, v1, u0, v0);
+    }
+    let du = u1 - u0;
+    let gradient = if du == 0. { 1. } else { (v1 - v0) / du };
+    // nothing past the canvas edges needs plotting
+    let (min, max) = if steep { (canvas.min_y(), canvas.max_y()) } else { (canvas.min_x(), canvas.max_x()) };
+
+    // covers the pixels across the line at `u` as far as the band of the
+    // given thickness around `v` overlaps them
+    let mut plot = |u: i32, v: f64, coverage: f64| {
+        let (low, high) = (v - thickness / 2., v + thickness / 2.);
+        let first = (low + 0.5).floor() as i32;
+        let last = (high + 0.5).ceil() as i32 - 1;
+        for i in first..=last {
+            let overlap = high.min(i as f64 + 0.5) - low.max(i as f64 - 0.5);
+            let (x, y) = if steep { (i, u) } else { (u, i) };
+            canvas.blend_pixel(x, y, color, coverage * overlap.max(0.));
+        }
+    };
+
+    let (start, end) = (u0.round(), u1.round());
+    let v_at = |u: f64| v0 + gradient * (u - u0);
+    if start == end {
+        plot(start as i32, v_at((u0 + u1) / 2.), du);
+        return;
+    }
+    // the endpoints only cover part of their pixels along the line
+    plot(start as i32, v_at(start), 0.5 - (u0 - start));
+    plot(end as i32, v_at(end), 0.5 + (u1 - end));
+
+    let first = (start as i64 + 1).max(min as i64);
+    let last = (end as i64 - 1).min(max as i64 - 1);
+    for u in first..=last {
+        plot(u as i32, v_at(u as f64), 1.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color::new(255, 255, 255);
+
+    fn lit(canvas: &Canvas) -> Vec<(i32, i32, u8)> {
+        let mut pixels: Vec<_> = canvas.image.enumerate_pixels()
+            .filter(|(_, _, p)| p[0] > 0)
+            .map(|(x, y, p)| {
+                let (x, y) = canvas.from_image_coords(x, y);
+                (x, y, p[0])
+            })
+            .collect();
+        pixels.sort();
+        pixels
+    }
+
+    #[test]
+    fn aliased_lines() {
+        assert_eq!(interpolate(0, 0., 4, 2.), [0., 0.5, 1., 1.5, 2.]);
+        assert_eq!(interpolate(3, 1., 3, 5.), [1.]);
+
+        for algorithm in [LineAlgorithm::Interpolation, LineAlgorithm::Bresenham] {
+            let style = LineStyle { algorithm, thickness: 1. };
+            let mut canvas = Canvas::new(8, 8);
+            draw_line(&mut canvas, (3., 3.), (-3., -3.), WHITE, &style);
+            let diagonal: Vec<_> = (-3..=3).map(|i| (i, i, 255)).collect();
+            assert_eq!(lit(&canvas), diagonal, "{algorithm:?}");
+
+            let mut canvas = Canvas::new(8, 8);
+            draw_line(&mut canvas, (-4., 1.), (3., -2.), WHITE, &style);
+            let pixels = lit(&canvas);
+            assert_eq!(pixels.len(), 8, "{algorithm:?}");
+            assert_eq!((pixels[0], pixels[7]), ((-4, 1, 255), (3, -2, 255)));
+
+            let thick = LineStyle { algorithm, thickness: 3. };
+            let mut canvas = Canvas::new(8, 8);
+            draw_line(&mut canvas, (0., -100.), (0., 100.), WHITE, &thick);
+            assert_eq!(lit(&canvas).len(), 3 * 8, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn antialiased_lines() {
+        let style = LineStyle { algorithm: LineAlgorithm::Wu, thickness: 1. };
+        let mut canvas = Canvas::new(8, 8);
+        draw_line(&mut canvas, (-2., 0.), (2., 0.), WHITE, &style);
+        let full: Vec<_> = (-1..=1).map(|x| (x, 0, 255)).collect();
+        assert_eq!(lit(&canvas)[1..4], full);
+        assert_eq!((lit(&canvas)[0], lit(&canvas)[4]), ((-2, 0, 128), (2, 0, 128)));
+
+        // halfway between two rows
+        let mut canvas = Canvas::new(8, 8);
+        draw_line(&mut canvas, (-3., 0.5), (3., 0.5), WHITE, &style);
+        assert_eq!(canvas.get_pixel(0, 0), Some(Color::new(128, 128, 128)));
+        assert_eq!(canvas.get_pixel(0, 1), Some(Color::new(128, 128, 128)));
+
+        let thick = LineStyle { thickness: 2.5, ..style };
+        let mut canvas = Canvas::new(8, 8);
+        draw_line(&mut canvas, (0., -100.), (0., 100.), WHITE, &thick);
+        let row: Vec<_> = lit(&canvas).into_iter().filter(|p| p.1 == 0).collect();
+        assert_eq!(row, [(-1, 0, 191), (0, 0, 255), (1, 0, 191)]);
+    }
+
+    #[test]
+    fn clipped_lines() {
+        for algorithm in [LineAlgorithm::Interpolation, LineAlgorithm::Bresenham, LineAlgorithm::Wu] {
+            let style = LineStyle { algorithm, thickness: 1. };
+            let mut canvas = Canvas::new(8, 8);
+            draw_line(&mut canvas, (-3e9, 0.), (3e9, 0.), WHITE, &style);
+            draw_line(&mut canvas, (0., 0.), (2e7, 1.), WHITE, &style);
+            assert_eq!(lit(&canvas), (-4..4).map(|x| (x, 0, 255)).collect::<Vec<_>>(), "{algorithm:?}");
+
+            // clipping doesn't move the pixels that stay on the canvas
+            let mut small = Canvas::new(8, 8);
+            let mut large = Canvas::new(101, 101);
+            for canvas in [&mut small, &mut large] {
+                draw_line(canvas, (-50., -7.), (45., 20.), WHITE, &style);
+                draw_line(canvas, (3., 50.), (-2., -40.), WHITE, &style);
+            }
+            let inside: Vec<_> = lit(&large).into_iter()
+                .filter(|&(x, y, _)| (-4..4).contains(&x) && (-4..4).contains(&y))
+                .collect();
+            assert_eq!(lit(&small), inside, "{algorithm:?}");
+        }
+    }
+}
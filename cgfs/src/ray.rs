@@ -0,0 +1,81 @@
+use crate::math::*;
+use crate::material::Material;
+use crate::scene::{Instance, LocalHit, Sphere};
+use cgmath::prelude::*;
+
+/// Half line starting at `origin`. `direction` is not required to be
+/// normalized; hits are reported in multiples of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point,
+    pub direction: Vector,
+}
+
+impl Ray {
+    pub fn new(origin: Point, direction: Vector) -> Self {
+        Self {
+            origin, direction
+        }
+    }
+
+    pub fn at(&self, t: f64) -> Point {
+        self.origin + self.direction * t
+    }
+}
+
+/// What a ray is cast for, so objects can be hidden from some kinds, see
+/// [`Visibility`](crate::scene::Visibility).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    /// Primary rays from the camera.
+    Camera,
+    /// Reflected rays.
+    Reflection,
+    /// Rays testing whether light reaches a point.
+    Shadow,
+    /// Rays gathering light from emissive objects. They see what shadow rays
+    /// see, and emitters even if those don't cast shadows.
+    Emission,
+}
+
+/// Closest intersection of a ray with the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit<'a> {
+    /// Ray parameter, `point == ray.at(t)`.
+    pub t: f64,
+    /// Distance from the ray origin to `point`.
+    pub distance: f64,
+    pub point: Point,
+    /// Unit normal pointing out of the object.
+    pub normal: Vector,
+    /// Index of the object, see [`RayTracer::object`](crate::RayTracer::object).
+    pub object: usize,
+    /// The object that was hit, with its material.
+    pub sphere: &'a Sphere,
+    /// Instance whose material overrides the sphere's, if any.
+    pub instance: Option<&'a Instance>,
+    /// Texture coordinates, see [`Sphere::uv_at`].
+    pub uv: (f64, f64),
+}
+
+impl<'a> Hit<'a> {
+    pub(crate) fn new(ray: &Ray, hit: LocalHit<'a>) -> Self {
+        Self {
+            t: hit.t,
+            distance: hit.t * ray.direction.magnitude(),
+            point: ray.at(hit.t),
+            normal: hit.normal,
+            object: hit.object,
+            sphere: hit.sphere,
+            instance: hit.instance,
+            uv: hit.uv,
+        }
+    }
+
+    /// Material of the surface, taking instance overrides into account.
+    pub fn material(&self) -> &'a Material {
+        self.instance
+            .and_then(|i| i.material.as_ref())
+            .unwrap_or(&self.sphere.material)
+    }
+}
@@ -1,13 +1,27 @@
+use std::f64::consts::PI;
+use std::time::Instant;
+
 use cgmath::prelude::*;
-use cgmath::{Vector3, Matrix3};
-use image::RgbImage;
+use cgmath::Vector3;
+use image::{GrayImage, Luma, Rgb, Rgb32FImage, RgbImage};
+#[cfg(feature = "scene")]
 use toml::Value;
+use crate::sampler::SampleStream;
 use crate::{
     color::*,
     scene::*,
+    material::*,
+    post::*,
+    denoise::*,
+    quantize::*,
+    environment::*,
+    aov::*,
+    ray::*,
+    render::*,
     canvas::*,
     camera::*,
     math::*,
+    transform::rotation,
 };
 #[cfg(feature = "scene")]
 use crate::toml::FromToml;
@@ -18,174 +32,1007 @@ pub struct RayTracer {
     pub canvas: Canvas,
     pub camera: Camera,
     pub viewport: Viewport,
-    pub background: Color,
+    /// Color of rays that miss where no environment is seen, see
+    /// [`Environment::visible`]. `None` for a transparent background that
+    /// renders into [`Canvas::alpha`], reflections of it are black.
+    pub background: Option<Color>,
     pub spheres: Vec<Sphere>,
+    pub groups: Vec<Group>,
+    pub instances: Vec<Instance>,
     pub lights: Vec<Light>,
+    pub environment: Option<Environment>,
     pub recursion_depth: u32,
+    pub aovs: AovBuffers,
+    pub sampling: Sampling,
+    /// Filters the finished image, before `post`. Captures the normal,
+    /// depth and albedo it needs even if those AOVs aren't enabled.
+    pub denoise: Option<Denoiser>,
+    /// Applied to the finished image before it is written to the canvas.
+    pub post: PostProcess,
+    /// Converts the finished image into 8-bit channels.
+    pub quantize: Quantizer,
+    /// Collects [`RenderStats`] while set.
+    pub stats: Option<StatsCollector>,
 }
 
 
 
 impl RayTracer {
+    /// Empty scene on a black background, seen from the origin looking
+    /// along `+z` through [`Viewport::fit`].
+    pub fn new(canvas: Canvas) -> Self {
+        Self {
+            viewport: Viewport::fit(&canvas),
+            canvas,
+            camera: Camera::default(),
+            background: Some(color(0, 0, 0)),
+            spheres: Vec::new(),
+            groups: Vec::new(),
+            instances: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+            recursion_depth: 3,
+            aovs: AovBuffers::default(),
+            sampling: Sampling::default(),
+            denoise: None,
+            post: PostProcess::default(),
+            quantize: Quantizer::default(),
+            stats: None,
+        }
+    }
+
     #[cfg(feature = "scene")]
     pub fn from_description(toml: &str) -> Result<Self, String> {
         let toml: Value = toml::from_str(toml).map_err(|e| e.to_string())?;
         Self::from_toml(&toml)
     }
     pub fn render(&mut self) -> &RgbImage {
-        for x in self.canvas.min_x()..self.canvas.max_x() {
-            for y in self.canvas.min_y()..self.canvas.max_y() {
-                let ray = self.canvas_to_viewport(x, y);
-                let color = self.trace_ray(self.camera.position, ray, (self.viewport.distance, f64::INFINITY), self.recursion_depth);
-                self.canvas.put_pixel(x, y, color);
+        self.render_with(&RenderContext::default());
+        self.image()
+    }
+
+    /// Like [`RayTracer::render`], but reports progress through `ctx` and stops
+    /// early once its cancel token is set.
+    pub fn render_with(&mut self, ctx: &RenderContext) -> RenderStatus {
+        self.render_progressive_with(&Progressive::scanlines(), ctx, |_, _| {})
+    }
+
+    /// Renders tile by tile, calling `on_tile` with the image as soon as each
+    /// tile is done, so the partial result can be displayed.
+    pub fn render_progressive<F>(&mut self, progressive: &Progressive, on_tile: F) -> &RgbImage
+    where
+        F: FnMut(&RgbImage, Tile),
+    {
+        self.render_progressive_with(progressive, &RenderContext::default(), on_tile);
+        self.image()
+    }
+
+    /// [`RayTracer::render_progressive`] with progress reporting and
+    /// cancellation, checked after every row of blocks.
+    pub fn render_progressive_with<F>(&mut self, progressive: &Progressive, ctx: &RenderContext, mut on_tile: F) -> RenderStatus
+    where
+        F: FnMut(&RgbImage, Tile),
+    {
+        if let Some(stats) = &self.stats {
+            stats.reset();
+        }
+        let setup_start = now();
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let passes: Vec<Vec<Tile>> = (0..progressive.passes.len())
+            .map(|pass| progressive.tiles(width, height, pass))
+            .collect();
+        ctx.progress.start(passes.iter().flatten().map(Tile::rays).sum());
+
+        let mut aovs = std::mem::take(&mut self.aovs);
+        aovs.resize(width, height);
+        // guides of the denoiser the caller didn't ask for
+        let mut guides = Vec::new();
+        if self.denoise.is_some() {
+            for aov in [Aov::Normal, Aov::Depth, Aov::Albedo] {
+                if !aovs.is_enabled(aov) {
+                    aovs.enable(aov, width, height);
+                    guides.push(aov);
+                }
             }
         }
-        self.image()
+        let capture_aovs = aovs.any_enabled();
+        // unquantized pixels for the denoiser, the post stages and dithering
+        let mut pixels = (self.denoise.is_some() || !self.post.is_empty() || !self.quantize.is_default())
+            .then(|| Rgb32FImage::new(width, height));
+        self.canvas.alpha = self.miss_is_transparent().then(|| GrayImage::new(width, height));
+        // pixels traced by an earlier pass, which later passes keep
+        let mut exact = vec![false; width as usize * height as usize];
+        let mut status = RenderStatus::Finished;
+        self.time(Phase::Setup, setup_start);
+
+        'render: for tile in passes.into_iter().flatten() {
+            let tracing_start = self.stats.as_ref().and_then(|_| now());
+            let block = tile.block_size;
+            for by in (tile.y..tile.y + tile.height).step_by(block as usize) {
+                if ctx.cancel.is_cancelled() {
+                    status = RenderStatus::Cancelled;
+                    break 'render;
+                }
+                for bx in (tile.x..tile.x + tile.width).step_by(block as usize) {
+                    ctx.progress.advance(1);
+                    let index = |ix: u32, iy: u32| iy as usize * width as usize + ix as usize;
+                    if exact[index(bx, by)] {
+                        continue;
+                    }
+                    let (x, y) = self.canvas.from_image_coords(bx, by);
+                    let Pixel { color: value, alpha, samples, aov } = self.render_pixel(x, y, capture_aovs);
+                    let color = value.to_color();
+
+                    let rgb = Rgb([color.r, color.g, color.b]);
+                    let alpha = Luma([(alpha * 255.).round() as u8]);
+                    for iy in by..(by + block).min(tile.y + tile.height) {
+                        for ix in bx..(bx + block).min(tile.x + tile.width) {
+                            if exact[index(ix, iy)] {
+                                continue;
+                            }
+                            self.canvas.image.put_pixel(ix, iy, rgb);
+                            if let Some(canvas_alpha) = &mut self.canvas.alpha {
+                                canvas_alpha.put_pixel(ix, iy, alpha);
+                            }
+                            if let Some(pixels) = &mut pixels {
+                                pixels.put_pixel(ix, iy, Rgb([value.r as f32, value.g as f32, value.b as f32]));
+                            }
+                            if capture_aovs {
+                                aovs.record(ix, iy, aov);
+                                aovs.record_samples(ix, iy, samples);
+                            }
+                        }
+                    }
+                    exact[index(bx, by)] = true;
+                }
+            }
+            self.time(Phase::Tracing, tracing_start);
+
+            let callback_start = self.stats.as_ref().and_then(|_| now());
+            on_tile(&self.canvas.image, tile);
+            self.time(Phase::Callbacks, callback_start);
+        }
+        if let (Some(mut pixels), RenderStatus::Finished) = (pixels, status) {
+            if let (Some(denoiser), Some(normal), Some(depth), Some(albedo)) = (&self.denoise, &aovs.normal, &aovs.depth, &aovs.albedo) {
+                let denoise_start = self.stats.as_ref().and_then(|_| now());
+                pixels = denoiser.apply(&pixels, Guides { normal, depth, albedo });
+                self.time(Phase::Denoise, denoise_start);
+            }
+            let post_start = self.stats.as_ref().and_then(|_| now());
+            self.post.apply(&mut pixels);
+            self.time(Phase::Post, post_start);
+            let quantize_start = self.stats.as_ref().and_then(|_| now());
+            self.canvas.image = self.quantize.apply(&pixels);
+            self.time(Phase::Quantize, quantize_start);
+        }
+
+        for aov in guides {
+            aovs.disable(aov);
+        }
+        self.aovs = aovs;
+        status
     }
 
     pub fn image(&self) -> &RgbImage {
         &self.canvas.image
     }
 
-    fn canvas_to_viewport(&self, x: i32, y: i32) -> Vector {
+    /// Starts capturing `aov` on the next call to [`RayTracer::render`].
+    pub fn enable_aov(&mut self, aov: Aov) {
+        self.aovs.enable(aov, self.canvas.width(), self.canvas.height());
+    }
+
+    pub fn disable_aov(&mut self, aov: Aov) {
+        self.aovs.disable(aov);
+    }
+
+    /// Auxiliary outputs of the last render.
+    pub fn aovs(&self) -> &AovBuffers {
+        &self.aovs
+    }
+
+    /// Color of the pixel at canvas coordinates `(x, y)`, its coverage and
+    /// the number of samples it took. The color leaves out samples that see
+    /// a transparent background. With `capture_aov`, also what the first
+    /// sample hit.
+    fn render_pixel(&self, x: i32, y: i32, capture_aov: bool) -> Pixel {
+        let sampler = self.sampling.sampler.as_ref();
+        let trace = |index: u32, jitter: bool| {
+            self.count(Counter::PrimaryRays, 1);
+            let mut samples = SampleStream::new(sampler, (x, y), index);
+            let (dx, dy) = samples.next_2d();
+            let (dx, dy) = if jitter { (dx - 0.5, dy - 0.5) } else { (0., 0.) };
+
+            let ray = self.canvas_to_viewport(x as f64 + dx, y as f64 + dy);
+            let t_bounds = (self.viewport.distance, f64::INFINITY);
+            match self.closest_intersection(self.camera.position, ray, t_bounds, RayKind::Camera) {
+                Some(hit) => {
+                    let aov = (capture_aov && index == 0).then(|| self.aov_sample(&hit));
+                    let color = self.shade(&hit, ray, RayKind::Camera, self.recursion_depth, &mut samples);
+                    self.count(Counter::PathDepth, samples.deepest as u64);
+                    (Some(color), aov)
+                }
+                None => (self.miss(ray, RayKind::Camera), None),
+            }
+        };
+
+        if !self.sampling.is_supersampled() {
+            let (color, aov) = trace(0, false);
+            return match color {
+                Some(color) => Pixel { color, alpha: 1., samples: 1, aov },
+                None => Pixel { color: FloatColor::BLACK, alpha: 0., samples: 1, aov },
+            };
+        }
+        let samples = self.sampling.samples.max(1);
+
+        let mut sum = FloatColor::BLACK;
+        let mut covered = 0;
+        let mut estimate = PixelEstimate::default();
+        let mut aov = None;
+        loop {
+            let (color, sample_aov) = trace(estimate.n, true);
+            if estimate.n == 0 {
+                aov = sample_aov;
+            }
+            if let Some(color) = color {
+                sum += color;
+                covered += 1;
+            }
+            estimate.add(color.map_or(0., FloatColor::luminance));
+
+            match &self.sampling.adaptive {
+                _ if estimate.n < samples => continue,
+                Some(adaptive) if estimate.n < adaptive.max_samples && estimate.error() > adaptive.threshold => continue,
+                _ => break,
+            }
+        }
+        Pixel {
+            color: if covered > 0 { sum / covered as f64 } else { FloatColor::BLACK },
+            alpha: covered as f64 / estimate.n as f64,
+            samples: estimate.n,
+            aov,
+        }
+    }
+
+    /// Starts collecting statistics on the following renders.
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(StatsCollector::default);
+    }
+
+    /// Statistics of the last render, if collection is enabled.
+    pub fn render_stats(&self) -> Option<RenderStats> {
+        self.stats.as_ref().map(StatsCollector::snapshot)
+    }
+
+    fn count(&self, counter: Counter, n: u64) {
+        if let Some(stats) = &self.stats {
+            stats.count(counter, n);
+        }
+    }
+
+    fn time(&self, phase: Phase, since: Option<Instant>) {
+        if let Some(stats) = &self.stats {
+            stats.time(phase, since);
+        }
+    }
+
+    /// Object by index: the top level spheres come first, followed by the
+    /// contents of each group as numbered by [`Group::object`], and then the
+    /// geometry of each instance.
+    pub fn object(&self, index: usize) -> Option<&Sphere> {
+        object(&self.spheres, &self.groups, &self.instances, index)
+    }
+
+    /// Closest object visible to `kind` hit by the ray from `origin` along
+    /// `direction`.
+    pub fn cast_ray(&self, origin: Point, direction: Vector, kind: RayKind) -> Option<Hit<'_>> {
+        self.closest_intersection(origin, direction, (0.001, f64::INFINITY), kind)
+    }
+
+    /// Ray leaving the camera through canvas coordinates `(x, y)`, which are
+    /// centered like in [`Canvas::put_pixel`].
+    pub fn camera_ray(&self, x: i32, y: i32) -> Ray {
+        Ray::new(self.camera.position, self.canvas_to_viewport(x as f64, y as f64))
+    }
+
+    /// What the camera sees at canvas coordinates `(x, y)`.
+    pub fn pick(&self, x: i32, y: i32) -> Option<Hit<'_>> {
+        let ray = self.camera_ray(x, y);
+        self.closest_intersection(ray.origin, ray.direction, (self.viewport.distance, f64::INFINITY), RayKind::Camera)
+    }
+
+    /// What a primary ray saw at `hit`, for the AOVs.
+    fn aov_sample(&self, hit: &Hit) -> AovSample {
+        AovSample {
+            // the unrotated ray has a z of `viewport.distance`
+            depth: hit.t * self.viewport.distance,
+            normal: hit.normal,
+            object: hit.object,
+            albedo: hit.material().color_at(hit.uv).to_color(),
+            position: hit.point,
+        }
+    }
+
+    fn canvas_to_viewport(&self, x: f64, y: f64) -> Vector {
         let v = Vector3::new(
-            x as f64 * (self.viewport.width / self.canvas.width() as f64),
-            y as f64 * (self.viewport.height as f64 / self.canvas.height() as f64),
+            x * (self.viewport.width / self.canvas.width() as f64),
+            y * (self.viewport.height / self.canvas.height() as f64),
             self.viewport.distance
         );
 
-        rotate_cam_ray(v, self.camera.rot_x, self.camera.rot_y, self.camera.rot_z)
+        rotation(vector(self.camera.rot_x, self.camera.rot_y, self.camera.rot_z)) * v
     }
 
-    fn trace_ray(&self, origin: Point, ray: Vector, t_bounds: (f64, f64), recursion_depth: u32) -> Color {
-        let closest_intersection = self.closest_intersection(origin, ray, t_bounds);
+    /// Radiance arriving along `ray`, see [`RayTracer::keeps_hdr`].
+    fn trace_ray(&self, origin: Point, ray: Vector, t_bounds: (f64, f64), kind: RayKind, recursion_depth: u32, samples: &mut SampleStream) -> FloatColor {
+        samples.deepest = samples.deepest.max(self.recursion_depth.saturating_sub(recursion_depth));
+        match self.closest_intersection(origin, ray, t_bounds, kind) {
+            Some(hit) => self.shade(&hit, ray, kind, recursion_depth, samples),
+            None => self.miss(ray, kind).unwrap_or(FloatColor::BLACK),
+        }
+    }
 
-        if let Some((sphere, t)) = closest_intersection {
-            let p = origin + t * ray;
-            let n = (p - sphere.pos).normalize();
+    /// Radiance leaving the surface `hit` by `ray`.
+    fn shade(&self, hit: &Hit, ray: Vector, kind: RayKind, recursion_depth: u32, samples: &mut SampleStream) -> FloatColor {
+        let (p, n) = (hit.point, hit.normal);
+        let material = hit.material();
+        if material.model == ShadingModel::Pbr {
+            return self.clamp(self.shade_pbr(hit, -ray, kind, recursion_depth, samples) + material.emission);
+        }
+        let reflective = material.reflective;
+        let hdr = self.keeps_hdr();
 
-            let local_color = sphere.color * self.compute_lighting(p, n, -ray, sphere.specular);
+        let lighting = self.compute_lighting(hit, -ray, samples);
+        let base = material.color_at(hit.uv);
+        let local_color = if hdr {
+            base * lighting
+        } else {
+            FloatColor::from(base.to_color() * lighting)
+        };
 
-            if recursion_depth <= 0 || sphere.reflective <= 0. {
-                return local_color;
+        let alpha = material.roughness_at(hit.uv).clamp(0., 1.).powi(2);
+        let color = if recursion_depth == 0 || reflective <= 0. {
+            local_color
+        } else if alpha <= 0. {
+            let r = reflect_ray(-ray, n);
+            self.count(Counter::ReflectionRays, 1);
+            let reflected_color = self.trace_ray(p, r, (0.001, f64::INFINITY), RayKind::Reflection, recursion_depth-1, samples);
+
+            if hdr {
+                local_color * (1.0 - reflective) + reflected_color * reflective
+            } else {
+                FloatColor::from(local_color.to_color() * (1.0 - reflective) + reflected_color.to_color() * reflective)
             }
+        } else {
+            let reflected_color = self.trace_glossy(hit, -ray, alpha, kind, recursion_depth, samples);
+            self.clamp(local_color * (1.0 - reflective) + reflected_color * reflective)
+        };
 
-            let r = reflect_ray(-ray, n);
-            let reflected_color = self.trace_ray(p, r, (0.001, f64::INFINITY), recursion_depth-1);
+        self.clamp(color + material.emission)
+    }
 
-            local_color * (1.0 - sphere.reflective) + reflected_color * sphere.reflective
+    /// Whether radiance stays unclamped through every bounce, for the post
+    /// stages that need values above `1` and for dithering. Otherwise each
+    /// bounce is quantized to 8 bits, like scenes have always rendered.
+    fn keeps_hdr(&self) -> bool {
+        self.post.needs_hdr() || !self.quantize.is_default()
+    }
+
+    /// `c` quantized like the canvas would, unless [`RayTracer::keeps_hdr`].
+    fn clamp(&self, c: FloatColor) -> FloatColor {
+        if self.keeps_hdr() {
+            c
         } else {
-            self.background
+            FloatColor::from(c.to_color())
         }
+    }
 
+    /// Whether camera rays that hit nothing leave the pixel transparent.
+    fn miss_is_transparent(&self) -> bool {
+        let environment_visible = self.environment.as_ref().is_some_and(|env| env.visible);
+        self.background.is_none() && !environment_visible
     }
+
+    /// What a `kind` ray that hits nothing sees, `None` if it's transparent.
+    /// Only camera rays skip an environment that isn't visible.
+    fn miss(&self, ray: Vector, kind: RayKind) -> Option<FloatColor> {
+        match &self.environment {
+            Some(env) if env.visible || kind != RayKind::Camera => Some(self.clamp(env.radiance(ray))),
+            _ => self.background.map(FloatColor::from),
+        }
+    }
+
     fn closest_intersection(
         &self,
         origin: Point,
         ray: Vector,
         t_bounds: (f64, f64),
-    ) -> Option<(&Sphere, f64)> {
-        let mut closest_t = f64::INFINITY;
-        let mut closest_sphere = None;
-
-        for sphere in &self.spheres {
-            let (t1, t2) = match sphere.intersect_ray(origin, ray) {
-                Some(t) => t,
-                None => continue,
-            };
+        kind: RayKind,
+    ) -> Option<Hit<'_>> {
+        let mut tests = 0;
+        let hit = closest_hit(&self.spheres, &self.groups, &self.instances, 0, origin, ray, t_bounds, kind, &mut tests);
+        self.count(Counter::IntersectionTests, tests);
 
-            if t1 > t_bounds.0 && t1 < t_bounds.1 && t1 < closest_t {
-                closest_t = t1;
-                closest_sphere = Some(sphere);
-            }
-            if t2 > t_bounds.0 && t2 < t_bounds.1 && t2 < closest_t {
-                closest_t = t2;
-                closest_sphere = Some(sphere);
-            }
-        }
-        closest_sphere.map(|s| (s, closest_t))
+        hit.map(|h| Hit::new(&Ray::new(origin, ray), h))
     }
     fn compute_lighting(
         &self,
-        point: Point,
-        normal: Vector,
+        hit: &Hit,
         v: Vector,
-        s: f64,
-    ) -> f64 {
+        samples: &mut SampleStream,
+    ) -> FloatColor {
+        let (point, normal, material) = (hit.point, hit.normal, hit.material());
         let mut i = 0.;
+        let mut path = None;
 
         for light in &self.lights {
             let curr_i: f64;
             let l: Vector;
             let t_max: f64;
+            let light_shadows: bool;
 
+            if !self.links_reach(light.links(), hit, &mut path) {
+                continue;
+            }
             match *light {
-                Light::Ambient { intensity } => {
+                Light::Ambient { intensity, .. } => {
                     i += intensity;
                     continue;
                 }
                 Light::Directional {
                     intensity,
                     direction,
+                    shadows,
+                    ..
                 } => {
                     curr_i = intensity;
                     l = direction * -1.;
                     t_max = f64::INFINITY;
+                    light_shadows = shadows;
                 }
-                Light::Point { intensity, pos } => {
+                Light::Point { intensity, pos, shadows, .. } => {
                     curr_i = intensity;
                     l = pos - point;
                     t_max = 1.;
+                    light_shadows = shadows;
+                }
+            }
+
+            if light_shadows && self.in_shadow(hit, l, t_max) {
+                continue;
+            }
+
+            let (diffuse, specular) = material.model.shade(normal, l, v, material.specular);
+            i += curr_i * diffuse;
+            i += curr_i * specular;
+        }
+
+        let mut i = FloatColor::splat(i);
+        i += self.compute_emitted_lighting(hit, &mut path, samples);
+        if let Some(env) = self.environment.as_ref().filter(|env| self.links_reach(&env.links, hit, &mut path)) {
+            let s = if material.model.has_specular() { material.specular } else { 0. };
+            i += self.compute_environment_lighting(env, hit, v, s, samples);
+        }
+        i
+    }
+
+    /// Radiance leaving a [`ShadingModel::Pbr`] surface towards `v`: a
+    /// Lambert diffuse lobe and a GGX specular lobe weighted by Schlick's
+    /// Fresnel term. Besides the lights, the specular lobe gathers the scene
+    /// through reflection rays sampled from it, see
+    /// [`Material::reflection_samples`].
+    fn shade_pbr(&self, hit: &Hit, v: Vector, kind: RayKind, recursion_depth: u32, samples: &mut SampleStream) -> FloatColor {
+        let material = hit.material();
+        let (point, n, v) = (hit.point, hit.normal, v.normalize());
+
+        let base = material.color_at(hit.uv);
+        let metallic = material.metallic_at(hit.uv).clamp(0., 1.);
+        let alpha = material.roughness_at(hit.uv).clamp(0., 1.).powi(2);
+        // a perfect mirror has no highlight from point lights
+        let light_alpha = alpha.max(MIN_LIGHT_ALPHA);
+
+        let f0 = FloatColor::splat(0.04) * (1. - metallic) + base * metallic;
+        let diffuse_color = base * (1. - metallic);
+        let n_dot_v = n.dot(v).max(1e-4);
+
+        let mut color = FloatColor::BLACK;
+        let mut path = None;
+        for light in &self.lights {
+            if !self.links_reach(light.links(), hit, &mut path) {
+                continue;
+            }
+            let (intensity, l, t_max, shadows) = match *light {
+                Light::Ambient { intensity, .. } => {
+                    color += diffuse_color * intensity;
+                    continue;
                 }
+                Light::Directional { intensity, direction, shadows, .. } => (intensity, -direction, f64::INFINITY, shadows),
+                Light::Point { intensity, pos, shadows, .. } => (intensity, pos - point, 1., shadows),
+            };
+
+            let n_dot_l = n.dot(l.normalize());
+            if n_dot_l <= 0. || (shadows && self.in_shadow(hit, l, t_max)) {
+                continue;
             }
 
-            if let Some(_) = self.closest_intersection(point, l, (0.001, t_max)) {
+            let l = l.normalize();
+            let h = (l + v).normalize();
+            let f = fresnel_schlick(f0, v.dot(h));
+            let specular = f * (ggx_distribution(n.dot(h), light_alpha)
+                * smith_masking(n_dot_l, n_dot_v, light_alpha)
+                / (4. * n_dot_l * n_dot_v));
+            let diffuse = diffuse_color * (FloatColor::splat(1.) - f) / PI;
+            // scaled by pi so a white dielectric is as bright as Lambert
+            color += (diffuse + specular) * (PI * intensity * n_dot_l);
+        }
+
+        color += diffuse_color * self.compute_emitted_lighting(hit, &mut path, samples);
+        if let Some(env) = self.environment.as_ref().filter(|env| self.links_reach(&env.links, hit, &mut path)) {
+            color += diffuse_color * self.compute_environment_lighting(env, hit, v, 0., samples);
+        }
+
+        if recursion_depth > 0 {
+            let count = reflection_samples(material, alpha, kind);
+            let mut reflected = FloatColor::BLACK;
+            for _ in 0..count {
+                let (u1, u2) = samples.next_2d();
+                let h = if alpha > 0. { sample_ggx(n, alpha, u1, u2) } else { n };
+                let l = reflect_ray(v, h);
+                let (n_dot_l, v_dot_h) = (n.dot(l), v.dot(h));
+
+                if n_dot_l > 0. && v_dot_h > 0. {
+                    let f = fresnel_schlick(f0, v_dot_h);
+                    // brdf * cos / pdf of the sampled direction
+                    let weight = if alpha > 0. {
+                        f * (smith_masking(n_dot_l, n_dot_v, alpha) * v_dot_h / (n_dot_v * n.dot(h)))
+                    } else {
+                        f
+                    };
+                    self.count(Counter::ReflectionRays, 1);
+                    let ray_color = self.trace_ray(point, l, (0.001, f64::INFINITY), RayKind::Reflection, recursion_depth - 1, samples);
+                    reflected += ray_color * weight;
+                }
+            }
+            color += reflected / count as f64;
+        }
+        color
+    }
+
+    /// Average of reflection rays perturbed around the mirror direction by
+    /// GGX microfacet normals of width `alpha`, the squared roughness at the
+    /// hit. Rays that end up below the surface count as black.
+    fn trace_glossy(&self, hit: &Hit, v: Vector, alpha: f64, kind: RayKind, recursion_depth: u32, samples: &mut SampleStream) -> FloatColor {
+        let count = reflection_samples(hit.material(), alpha, kind);
+
+        let mut sum = FloatColor::BLACK;
+        for _ in 0..count {
+            let (u1, u2) = samples.next_2d();
+            let h = sample_ggx(hit.normal, alpha, u1, u2);
+            let r = reflect_ray(v, h);
+            if r.dot(hit.normal) <= 0. {
                 continue;
             }
+            self.count(Counter::ReflectionRays, 1);
+            sum += self.trace_ray(hit.point, r, (0.001, f64::INFINITY), RayKind::Reflection, recursion_depth - 1, samples);
+        }
+        sum / count as f64
+    }
+
+    /// Whether an object visible to shadow rays blocks the light arriving at
+    /// `hit` along `l` before `t_max`. Objects that don't receive shadows are
+    /// never in shadow.
+    fn in_shadow(&self, hit: &Hit, l: Vector, t_max: f64) -> bool {
+        if !hit.sphere.receive_shadows {
+            return false;
+        }
+        self.count(Counter::ShadowRays, 1);
+        self.closest_intersection(hit.point, l, (0.001, t_max), RayKind::Shadow).is_some()
+    }
+
+    /// Monte Carlo estimate of the diffuse light emissive objects send to
+    /// `point`, from cosine distributed rays. Only gathered when pixels are
+    /// supersampled, so the noise averages out.
+    fn compute_emitted_lighting<'a>(&'a self, hit: &Hit<'a>, path: &mut Option<Vec<&'a str>>, samples: &mut SampleStream) -> FloatColor {
+        let count = self.sampling.emission_samples;
+        if count == 0 || !self.sampling.is_supersampled() || !any_emissive(&self.spheres, &self.groups, &self.instances) {
+            return FloatColor::BLACK;
+        }
+        let (point, normal) = (hit.point, hit.normal.normalize());
 
-            let n_dot_l = normal.dot(l);
-            if n_dot_l > 0. {
-                i += curr_i * n_dot_l / (normal.magnitude() * l.magnitude());
+        let mut sum = FloatColor::BLACK;
+        for _ in 0..count {
+            let (u1, u2) = samples.next_2d();
+            let l = sample_phong_lobe(normal, 1., u1, u2);
+            self.count(Counter::ShadowRays, 1);
+            // the cosine and the density cancel out
+            if let Some(emitter) = self.closest_intersection(point, l, (0.001, f64::INFINITY), RayKind::Emission) {
+                if self.links_reach(&emitter.sphere.emission_links, hit, path) {
+                    sum += emitter.material().emission;
+                }
+            }
+        }
+        sum / count as f64
+    }
+
+    /// Whether a light with `links` reaches `hit`. `path` caches the names
+    /// of `hit` for the following lights.
+    fn links_reach<'a>(&'a self, links: &LightLinks, hit: &Hit<'a>, path: &mut Option<Vec<&'a str>>) -> bool {
+        links.is_empty() || links.lights_path(path.get_or_insert_with(|| {
+            let mut path = Vec::new();
+            object_path(&self.spheres, &self.groups, &self.instances, hit.object, &mut path);
+            path.push(&hit.sphere.name);
+            path
+        }))
+    }
+
+    /// Monte Carlo estimate of the light an environment map sends towards
+    /// `v`. The diffuse part samples the map by brightness, the glossy part
+    /// samples a normalized Phong lobe around the mirror direction.
+    fn compute_environment_lighting(
+        &self,
+        env: &Environment,
+        hit: &Hit,
+        v: Vector,
+        s: f64,
+        samples: &mut SampleStream,
+    ) -> FloatColor {
+        if env.samples == 0 {
+            return FloatColor::BLACK;
+        }
+        let normal = hit.normal.normalize();
+        let visible = |dir: Vector| !self.in_shadow(hit, dir, f64::INFINITY);
+
+        let mut diffuse = FloatColor::BLACK;
+        let mut glossy = FloatColor::BLACK;
+        for _ in 0..env.samples {
+            let (u1, u2) = samples.next_2d();
+            if let Some((l, pdf)) = env.sample(u1, u2) {
+                let n_dot_l = normal.dot(l);
+                if n_dot_l > 0. && pdf > 0. && visible(l) {
+                    diffuse += env.radiance(l) * (n_dot_l / (PI * pdf));
+                }
             }
 
             if s > 0. {
-                let r = reflect_ray(l, normal);
-                let r_dot_v = r.dot(v);
-                if r_dot_v > 0. {
-                    i += curr_i * (r_dot_v / (r.magnitude() * v.magnitude())).powf(s);
+                let (u1, u2) = samples.next_2d();
+                let l = sample_phong_lobe(reflect_ray(v, normal).normalize(), s, u1, u2);
+                if normal.dot(l) > 0. && visible(l) {
+                    glossy += env.radiance(l);
                 }
             }
         }
-        i
+        // the lobe of `specular` integrates to 2π / (s + 1) over the
+        // sphere, and radiance π gives the intensity of one light
+        let glossy = glossy * (2. / (s + 1.));
+        (diffuse + glossy) / env.samples as f64
     }
 
 }
 
 
 
+/// What [`RayTracer::render_pixel`] found for one pixel.
+struct Pixel {
+    color: FloatColor,
+    /// Fraction of the samples that didn't see a transparent background.
+    alpha: f64,
+    samples: u32,
+    /// What the first sample hit, if asked for.
+    aov: Option<AovSample>,
+}
+
 fn reflect_ray(ray: Vector, n: Vector) -> Vector {
     n * n.dot(ray) * 2. - ray
 }
 
 
-fn rotate_cam_ray(v: Vector, x: f64, y: f64, z: f64) -> Vector {
-    let (x, y, z) = (x.to_radians(), y.to_radians(), z.to_radians());
-    let x_matrix = |x: f64| Matrix3::new(
-        1., 0., 0.,
-        0., x.cos(), -x.sin(),
-        0., x.sin(), x.cos());
-    let y_matrix = |y: f64| Matrix3::new(
-        y.cos(), 0., y.sin(),
-        0., 1., 0.,
-        -y.sin(), 0., y.cos());
-    let z_matrix = |z: f64| Matrix3::new(
-        z.cos(), -z.sin(), 0.,
-        z.sin(), z.cos(), 0.,
-        0., 0., 1.);
-
-    let v0 = z_matrix(z) * v;
-    let v1 = x_matrix(x) * v0;
-    let v2 = y_matrix(y) * v1;
-
-    v2
-}
\ No newline at end of file
+/// Smallest GGX alpha used for lights, which are points and would otherwise
+/// give an infinitely small, infinitely bright highlight on smooth surfaces.
+const MIN_LIGHT_ALPHA: f64 = 0.002;
+
+/// Reflection rays to average at a hit by a `kind` ray: only the first
+/// bounce of a rough surface splits into several.
+fn reflection_samples(material: &Material, alpha: f64, kind: RayKind) -> u32 {
+    if alpha > 0. && kind == RayKind::Camera {
+        material.reflection_samples.max(1)
+    } else {
+        1
+    }
+}
+
+/// GGX normal distribution for the cosine between normal and half vector.
+fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+    if n_dot_h <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// Separable Smith shadowing-masking term for GGX.
+fn smith_masking(n_dot_l: f64, n_dot_v: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let g1 = |c: f64| 2. * c / (c + (a2 + (1. - a2) * c * c).sqrt());
+    g1(n_dot_l) * g1(n_dot_v)
+}
+
+fn fresnel_schlick(f0: FloatColor, cos: f64) -> FloatColor {
+    let k = (1. - cos.clamp(0., 1.)).powi(5);
+    f0 * (1. - k) + FloatColor::splat(k)
+}
+
+/// Microfacet normal around `n` distributed like the GGX distribution
+/// times the cosine to `n`.
+fn sample_ggx(n: Vector, alpha: f64, u1: f64, u2: f64) -> Vector {
+    let tan2 = alpha * alpha * u1 / (1. - u1).max(1e-12);
+    let cos_a = 1. / (1. + tan2).sqrt();
+    around_axis(n, cos_a, 2. * PI * u2)
+}
+
+/// Direction around `axis` distributed like `cos^s` of the angle to it.
+fn sample_phong_lobe(axis: Vector, s: f64, u1: f64, u2: f64) -> Vector {
+    let cos_a = u1.powf(1. / (s + 1.));
+    around_axis(axis, cos_a, 2. * PI * u2)
+}
+
+/// Unit direction at angle `acos(cos_a)` from the unit vector `axis`, turned
+/// by `phi` around it.
+fn around_axis(axis: Vector, cos_a: f64, phi: f64) -> Vector {
+    let sin_a = (1. - cos_a * cos_a).max(0.).sqrt();
+
+    let helper = if axis.x.abs() > 0.9 { vector(0., 1., 0.) } else { vector(1., 0., 0.) };
+    let t = axis.cross(helper).normalize();
+    let b = axis.cross(t);
+
+    t * (sin_a * phi.cos()) + b * (sin_a * phi.sin()) + axis * cos_a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::texture::Texture;
+    use crate::transform::Transform;
+
+    fn white() -> Material {
+        Material::new(color(255, 255, 255))
+    }
+
+    fn ambient(intensity: f64) -> Light {
+        Light::Ambient { intensity, links: LightLinks::default() }
+    }
+
+    #[test]
+    fn post_sees_unclamped_values() {
+        let mut rt = RayTracer::new(Canvas::new(2, 2));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 10., white()));
+        rt.lights.push(ambient(3.));
+        rt.post = PostProcess::new(vec![PostStage::Exposure { stops: -2. }]);
+        // 3 scaled by 1/4, not 1 scaled by 1/4
+        assert_eq!(rt.render().get_pixel(0, 0)[0], 191);
+    }
+
+    #[test]
+    fn supersamples_average_unquantized() {
+        let mut rt = RayTracer::new(Canvas::new(2, 2));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 10., white()));
+        // far below one 8-bit step per sample, brought back up by exposure
+        rt.lights.push(ambient(0.001));
+        rt.sampling.samples = 4;
+        rt.post = PostProcess::new(vec![PostStage::Exposure { stops: 8. }]);
+        assert_eq!(rt.render().get_pixel(0, 0)[0], 65);
+    }
+
+    #[test]
+    fn aovs_reuse_primary_hits() {
+        let mut rt = RayTracer::new(Canvas::new(4, 4));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., Material::new(color(255, 0, 0))));
+        rt.enable_stats();
+        rt.render();
+        let plain = rt.render_stats().unwrap();
+
+        rt.enable_aov(Aov::Depth);
+        rt.enable_aov(Aov::Albedo);
+        rt.render();
+        let stats = rt.render_stats().unwrap();
+        assert_eq!(stats.intersection_tests, plain.intersection_tests);
+        // the pixel at the canvas origin
+        let aovs = rt.aovs();
+        assert!((aovs.depth.as_ref().unwrap().get_pixel(2, 1)[0] - 2.).abs() < 1e-6);
+        assert_eq!(aovs.albedo.as_ref().unwrap().get_pixel(2, 1).0, [1., 0., 0.]);
+    }
+
+    #[test]
+    fn progressive_passes_keep_traced_pixels() {
+        let mut rt = RayTracer::new(Canvas::new(16, 16));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., Material::new(color(255, 0, 0))));
+        rt.spheres.push(Sphere::new(point(0., 0., 20.), 15., Material::new(color(0, 0, 255))));
+        rt.lights.push(Light::Point { intensity: 1., pos: point(2., 1., 0.), shadows: true, links: LightLinks::default() });
+        let full = rt.render().clone();
+
+        rt.enable_stats();
+        let progressive = Progressive { tiling: Tiling::Tiles(8), passes: vec![4, 1] };
+        assert_eq!(rt.render_progressive(&progressive, |_, _| {}), &full);
+        assert_eq!(rt.render_stats().unwrap().primary_rays, 16 * 16);
+
+        // a coarse last pass still fills every AOV pixel
+        rt.enable_aov(Aov::Depth);
+        let progressive = Progressive { tiling: Tiling::Tiles(8), passes: vec![4, 2] };
+        rt.render_progressive(&progressive, |_, _| {});
+        assert!(rt.aovs().depth.as_ref().unwrap().pixels().all(|d| d[0] > 1.));
+    }
+
+    #[test]
+    fn environment_highlights_are_weighted() {
+        let mut rt = RayTracer::new(Canvas::new(1, 1));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., Material { specular: 100., ..white() }));
+        let mut env = Environment::new(Rgb32FImage::from_pixel(8, 4, Rgb([0.25, 0.25, 0.25]))).unwrap();
+        env.samples = 256;
+        env.visible = false;
+        rt.environment = Some(env);
+        // mostly the diffuse 0.25, the glossy lobe adds 0.25 * 2 / 101
+        let red = rt.render().get_pixel(0, 0)[0];
+        assert!((60..=72).contains(&red), "{red}");
+    }
+
+    #[test]
+    fn roughness_blurs_reflections() {
+        // a mirror reflecting a red ball behind the camera
+        let mirror = Material { reflective: 1., reflection_samples: 64, ..white() };
+        let mut rt = RayTracer::new(Canvas::new(32, 32));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., mirror));
+        rt.spheres.push(Sphere::new(point(0., 0., -3.), 1., Material::new(color(255, 0, 0))));
+        rt.lights.push(ambient(1.));
+        let red_pixels = |image: &RgbImage| image.pixels().filter(|p| p[0] > 8).count();
+        let sharp = rt.render().clone();
+
+        rt.spheres[0].material.roughness = 0.5;
+        let spread = red_pixels(rt.render());
+        assert!(spread > red_pixels(&sharp) * 2, "{spread} vs {}", red_pixels(&sharp));
+
+        // the texture scales the roughness back down to a mirror
+        let texture = Texture::new(Rgb32FImage::new(2, 2)).unwrap();
+        rt.spheres[0].material.roughness_texture = Some(Arc::new(texture));
+        assert_eq!(rt.render(), &sharp);
+    }
+
+    #[test]
+    fn light_links_match_groups_and_instances() {
+        let sphere = Sphere::new(point(0., 0., 3.), 1., white());
+        let ball = Group::new("ball".into(), Transform::default(), vec![sphere], Vec::new(), Vec::new());
+        let hero = Instance { name: "hero".into(), ..Instance::new(Arc::new(ball), Transform::default()) };
+        let props = Group::new("props".into(), Transform::default(), Vec::new(), Vec::new(), vec![hero]);
+        let mut rt = RayTracer::new(Canvas::new(1, 1));
+        rt.groups.push(props);
+        let lit = |rt: &mut RayTracer| rt.render().get_pixel(0, 0)[0] > 0;
+
+        let links = |include: &[&str], exclude: &[&str]| LightLinks {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        };
+        for (links, expected) in [
+            (links(&[], &[]), true),
+            (links(&[], &["props"]), false),
+            (links(&[], &["ball"]), false),
+            (links(&["hero"], &[]), true),
+            (links(&["villain"], &[]), false),
+        ] {
+            rt.lights = vec![Light::Ambient { intensity: 1., links: links.clone() }];
+            assert_eq!(lit(&mut rt), expected, "{links:?}");
+        }
+        rt.lights.clear();
+
+        // environment and emission take the same links
+        let mut env = Environment::new(Rgb32FImage::from_pixel(8, 4, Rgb([1., 1., 1.]))).unwrap();
+        env.visible = false;
+        env.links.exclude = vec!["props".into()];
+        rt.environment = Some(env.clone());
+        assert!(!lit(&mut rt));
+        env.links.exclude.clear();
+        rt.environment = Some(env);
+        assert!(lit(&mut rt));
+        rt.environment = None;
+
+        rt.sampling.samples = 2;
+        let glow = Material { emission: FloatColor::splat(1.), ..Material::new(color(0, 0, 0)) };
+        let mut glow = Sphere::new(point(0., 0., 0.), 100., glow);
+        glow.visibility.camera = false;
+        rt.spheres.push(glow);
+        assert!(lit(&mut rt));
+        rt.spheres[0].emission_links.exclude = vec!["hero".into()];
+        assert!(!lit(&mut rt));
+    }
+
+    #[test]
+    fn dithering_sees_unquantized_values() {
+        let mut rt = RayTracer::new(Canvas::new(8, 8));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 10., white()));
+        // half a level, which truncates to black
+        rt.lights.push(ambient(0.5 / 255.));
+        assert!(rt.render().pixels().all(|p| p[0] == 0));
+        rt.quantize.dither = Dither::Bayer { size: 4 };
+        let raised = rt.render().pixels().filter(|p| p[0] == 1).count();
+        assert_eq!(raised, 32);
+    }
+
+    #[test]
+    fn stats_average_path_depth() {
+        // a mirror facing the camera, with glossy splitting on its first bounce
+        let mirror = Material { reflective: 1., roughness: 0.5, reflection_samples: 8, ..white() };
+        let mut rt = RayTracer::new(Canvas::new(1, 1));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., mirror));
+        rt.recursion_depth = 2;
+        rt.enable_stats();
+        rt.render();
+        let stats = rt.render_stats().unwrap();
+        assert_eq!(stats.primary_rays, 1);
+        assert!(stats.reflection_rays > 2, "{}", stats.reflection_rays);
+        // rays leaving the mirror don't come back, every path is one bounce deep
+        assert_eq!(stats.average_depth(), 1.);
+    }
+
+    #[test]
+    fn color_texture_tints_every_model() {
+        let texture = Arc::new(Texture::new(Rgb32FImage::from_pixel(2, 2, Rgb([1., 0.5, 0.]))).unwrap());
+        let mut rt = RayTracer::new(Canvas::new(1, 1));
+        rt.lights.push(ambient(1.));
+        for model in [ShadingModel::Lambert, ShadingModel::Phong, ShadingModel::Toon { bands: 2 }, ShadingModel::Pbr] {
+            let material = Material { model, color_texture: Some(texture.clone()), ..white() };
+            rt.spheres = vec![Sphere::new(point(0., 0., 3.), 1., material)];
+            assert_eq!(rt.render().get_pixel(0, 0).0, [255, 127, 0], "{model:?}");
+        }
+    }
+
+    #[test]
+    fn emitters_light_without_casting_shadows() {
+        // a glowing shell around the scene, hidden from the camera and shadows
+        let glow = Material { emission: FloatColor::splat(1.), ..Material::new(color(0, 0, 0)) };
+        let mut shell = Sphere::new(point(0., 0., 0.), 100., glow);
+        shell.visibility = Visibility { camera: false, reflections: true, shadows: false };
+        let mut rt = RayTracer::new(Canvas::new(1, 1));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., white()));
+        rt.spheres.push(shell);
+        rt.sampling.samples = 4;
+        rt.sampling.emission_samples = 4;
+        let lit = rt.render().get_pixel(0, 0)[0];
+        assert!(lit > 200, "{lit}");
+
+        // occluders that don't cast shadows don't block it either
+        let mut veil = Sphere::new(point(0., 0., 0.), 50., white());
+        veil.visibility = Visibility { camera: false, reflections: false, shadows: false };
+        rt.spheres.push(veil);
+        assert_eq!(rt.render().get_pixel(0, 0)[0], lit);
+    }
+
+    #[test]
+    fn objects_and_camera_rotate_alike() {
+        let mut rt = RayTracer::new(Canvas::new(16, 16));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., Material::new(color(255, 0, 0))));
+        rt.spheres.push(Sphere::new(point(0.8, 0.5, 2.), 0.3, Material::new(color(0, 0, 255))));
+        rt.lights.push(ambient(1.));
+        let straight = rt.render().clone();
+
+        // turning the whole scene and the camera by the same angles changes nothing
+        let angles = vector(20., 35., -10.);
+        let spheres = std::mem::take(&mut rt.spheres);
+        rt.groups.push(Group::new("scene".into(), Transform::rotate(angles), spheres, Vec::new(), Vec::new()));
+        (rt.camera.rot_x, rt.camera.rot_y, rt.camera.rot_z) = (angles.x, angles.y, angles.z);
+        assert_eq!(rt.render(), &straight);
+    }
+
+    #[test]
+    fn hidden_environment_stays_in_reflections() {
+        let mut rt = RayTracer::new(Canvas::new(16, 16));
+        rt.spheres.push(Sphere::new(point(0., 0., 3.), 1., Material { reflective: 1., ..white() }));
+        let mut env = Environment::new(Rgb32FImage::from_pixel(8, 4, Rgb([0.5, 0.5, 0.5]))).unwrap();
+        env.samples = 0;
+        env.visible = false;
+        rt.environment = Some(env);
+        let image = rt.render();
+        // the corner misses the mirror, the center reflects the map
+        assert_eq!(image.get_pixel(0, 0).0, [0; 3]);
+        assert_eq!(image.get_pixel(8, 8).0, [127; 3]);
+    }
+}
@@ -0,0 +1,441 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::sampler::{IndependentSampler, Sampler};
+
+/// Rectangle of the image, in image coordinates (origin at the top left).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Index of the refinement pass the tile belongs to.
+    pub pass: usize,
+    /// Size of the blocks the tile was filled with in this pass; `1` means
+    /// the tile is at full resolution.
+    pub block_size: u32,
+}
+
+/// How the image is split into units of work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tiling {
+    /// Square tiles of the given size, row by row.
+    Tiles(u32),
+    /// One row of the image at a time.
+    Scanlines,
+}
+
+/// Settings for [`RayTracer::render_progressive`](crate::RayTracer::render_progressive).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progressive {
+    pub tiling: Tiling,
+    /// Block size of every pass. A pass traces one ray per block and fills
+    /// the whole block with its color and AOVs, so `[8, 1]` shows a coarse
+    /// preview before the full resolution image. Pixels an earlier pass
+    /// traced are kept rather than traced again.
+    pub passes: Vec<u32>,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Self {
+            tiling: Tiling::Tiles(32),
+            passes: vec![8, 1],
+        }
+    }
+}
+
+impl Progressive {
+    /// Full resolution scanlines in a single pass.
+    pub fn scanlines() -> Self {
+        Self {
+            tiling: Tiling::Scanlines,
+            passes: vec![1],
+        }
+    }
+
+    /// Tiles of one pass covering a `width` x `height` image.
+    pub(crate) fn tiles(&self, width: u32, height: u32, pass: usize) -> Vec<Tile> {
+        let block_size = self.passes[pass].max(1);
+        let (tile_w, tile_h) = match self.tiling {
+            Tiling::Tiles(size) => (size.max(1), size.max(1)),
+            Tiling::Scanlines => (width.max(1), 1),
+        };
+
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(tile_h as usize) {
+            for x in (0..width).step_by(tile_w as usize) {
+                tiles.push(Tile {
+                    x, y,
+                    width: tile_w.min(width - x),
+                    height: tile_h.min(height - y),
+                    pass,
+                    block_size,
+                });
+            }
+        }
+        tiles
+    }
+}
+
+impl Tile {
+    /// Number of blocks in the tile, at most one traced pixel each.
+    pub fn rays(&self) -> u64 {
+        let blocks = |len: u32| len.div_ceil(self.block_size) as u64;
+        blocks(self.width) * blocks(self.height)
+    }
+}
+
+/// Shared flag asking a render to stop. Clones refer to the same flag, so
+/// one can be kept by the UI while the render runs on another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so the token can be used for another render.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Shared counter of the rays a render has traced so far. Like
+/// [`CancelToken`], clones observe the same render.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    done: Arc<AtomicU64>,
+    total: Arc<AtomicU64>,
+    started: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Progress {
+    pub(crate) fn start(&self, total: u64) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+        *self.started.lock().unwrap() = now();
+    }
+
+    pub(crate) fn advance(&self, rays: u64) {
+        self.done.fetch_add(rays, Ordering::Relaxed);
+    }
+
+    /// Blocks done so far, over all passes. Coarse passes trace a single
+    /// pixel per block, and blocks whose pixel was already traced count as
+    /// done without tracing it again.
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Blocks in all passes of the render.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Completion in `[0, 1]`.
+    pub fn fraction(&self) -> f64 {
+        match self.total() {
+            0 => 0.,
+            total => self.done() as f64 / total as f64,
+        }
+    }
+
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.started.lock().unwrap().map(|s| s.elapsed())
+    }
+
+    /// Time left, extrapolated from the speed so far. `None` until something
+    /// has been traced.
+    pub fn remaining(&self) -> Option<Duration> {
+        let (done, total) = (self.done(), self.total());
+        if done == 0 {
+            return None;
+        }
+        let elapsed = self.elapsed()?;
+        Some(elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64))
+    }
+}
+
+/// Handles for observing and stopping a render from the outside.
+#[derive(Debug, Clone, Default)]
+pub struct RenderContext {
+    pub cancel: CancelToken,
+    pub progress: Progress,
+}
+
+/// How a render ended. A cancelled render leaves the tiles finished so far
+/// in the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderStatus {
+    Finished,
+    Cancelled,
+}
+
+/// Anti-aliasing settings: how many rays are averaged into every pixel.
+#[derive(Debug, Clone)]
+pub struct Sampling {
+    /// Rays per pixel. With a single sample the ray goes through the pixel
+    /// center, otherwise the samples are spread over the pixel.
+    /// In adaptive mode, the number every pixel starts with.
+    pub samples: u32,
+    pub adaptive: Option<Adaptive>,
+    /// Drives pixel positions and every other random decision.
+    pub sampler: Arc<dyn Sampler>,
+    /// Rays per shading point that gather light from emissive objects. They
+    /// are only traced when pixels are supersampled; with a single sample
+    /// per pixel emission only shows on the emitters themselves.
+    pub emission_samples: u32,
+}
+
+impl Sampling {
+    /// Whether pixels average several jittered samples.
+    pub fn is_supersampled(&self) -> bool {
+        self.samples > 1 || self.adaptive.is_some()
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            samples: 1,
+            adaptive: None,
+            sampler: Arc::new(IndependentSampler::new(0)),
+            emission_samples: 1,
+        }
+    }
+}
+
+/// Keeps adding samples to a pixel while its estimate is still noisy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adaptive {
+    /// Upper bound of samples per pixel.
+    pub max_samples: u32,
+    /// A pixel is done once the standard error of its mean luminance, in
+    /// `[0, 1]` units, falls below this.
+    pub threshold: f64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            max_samples: 64,
+            threshold: 0.01,
+        }
+    }
+}
+
+/// Running mean and variance of the luminance of a pixel's samples.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PixelEstimate {
+    pub n: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, luminance: f64) {
+        self.n += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    /// Standard error of the mean.
+    pub fn error(&self) -> f64 {
+        if self.n < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.n - 1) as f64;
+        (variance / self.n as f64).sqrt()
+    }
+}
+
+/// `Instant::now` panics on wasm, where timings are simply unavailable.
+pub(crate) fn now() -> Option<Instant> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Some(Instant::now());
+    #[cfg(target_arch = "wasm32")]
+    return None;
+}
+
+/// Counters gathered while rendering, see [`RenderStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Counter {
+    PrimaryRays,
+    ShadowRays,
+    ReflectionRays,
+    IntersectionTests,
+    /// Sum of the reflection depth every primary path reached.
+    PathDepth,
+}
+
+/// Phases of a render that are timed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Setup,
+    Tracing,
+    Callbacks,
+    Denoise,
+    Post,
+    Quantize,
+}
+
+/// Accumulates statistics while rendering. Enabled by setting
+/// [`RayTracer::stats`](crate::RayTracer::stats).
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    counters: [AtomicU64; 5],
+    nanos: [AtomicU64; 6],
+}
+
+impl Clone for StatsCollector {
+    fn clone(&self) -> Self {
+        let load = |a: &AtomicU64| AtomicU64::new(a.load(Ordering::Relaxed));
+        Self {
+            counters: self.counters.each_ref().map(load),
+            nanos: self.nanos.each_ref().map(load),
+        }
+    }
+}
+
+impl StatsCollector {
+    pub(crate) fn count(&self, counter: Counter, n: u64) {
+        self.counters[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Adds the time since `since` to `phase`.
+    pub(crate) fn time(&self, phase: Phase, since: Option<Instant>) {
+        if let Some(since) = since {
+            self.nanos[phase as usize].fetch_add(since.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn reset(&self) {
+        for a in self.counters.iter().chain(&self.nanos) {
+            a.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> RenderStats {
+        let counter = |c: Counter| self.counters[c as usize].load(Ordering::Relaxed);
+        let time = |p: Phase| Duration::from_nanos(self.nanos[p as usize].load(Ordering::Relaxed));
+        RenderStats {
+            primary_rays: counter(Counter::PrimaryRays),
+            shadow_rays: counter(Counter::ShadowRays),
+            reflection_rays: counter(Counter::ReflectionRays),
+            intersection_tests: counter(Counter::IntersectionTests),
+            path_depth: counter(Counter::PathDepth),
+            setup_time: time(Phase::Setup),
+            tracing_time: time(Phase::Tracing),
+            callback_time: time(Phase::Callbacks),
+            denoise_time: time(Phase::Denoise),
+            post_time: time(Phase::Post),
+            quantize_time: time(Phase::Quantize),
+        }
+    }
+}
+
+/// What a render spent its time on. Timings stay zero on wasm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderStats {
+    /// Rays leaving the camera, counting every sample.
+    pub primary_rays: u64,
+    /// Visibility rays towards lights and environment samples.
+    pub shadow_rays: u64,
+    /// Mirror rays spawned by reflective surfaces.
+    pub reflection_rays: u64,
+    /// Ray-object intersection tests of all kinds of rays.
+    pub intersection_tests: u64,
+    /// Sum over primary rays of the most reflections in a row their path
+    /// went through.
+    pub path_depth: u64,
+    /// Splitting the image into tiles and preparing buffers.
+    pub setup_time: Duration,
+    /// Tracing rays and filling the canvas.
+    pub tracing_time: Duration,
+    /// Time spent inside progressive rendering callbacks.
+    pub callback_time: Duration,
+    /// Filtering the finished image with the denoiser.
+    pub denoise_time: Duration,
+    /// Running the post stages.
+    pub post_time: Duration,
+    /// Converting the unquantized image into the canvas, only timed when
+    /// there is one, see [`RayTracer::post`](crate::RayTracer::post).
+    pub quantize_time: Duration,
+}
+
+impl RenderStats {
+    /// Average reflection depth reached by the path of each primary ray.
+    pub fn average_depth(&self) -> f64 {
+        match self.primary_rays {
+            0 => 0.,
+            n => self.path_depth as f64 / n as f64,
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.setup_time + self.tracing_time + self.callback_time + self.denoise_time + self.post_time + self.quantize_time
+    }
+}
+
+impl std::fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "primary rays:       {}", self.primary_rays)?;
+        writeln!(f, "shadow rays:        {}", self.shadow_rays)?;
+        writeln!(f, "reflection rays:    {}", self.reflection_rays)?;
+        writeln!(f, "intersection tests: {}", self.intersection_tests)?;
+        writeln!(f, "average depth:      {:.3}", self.average_depth())?;
+        writeln!(f, "setup:              {:?}", self.setup_time)?;
+        writeln!(f, "tracing:            {:?}", self.tracing_time)?;
+        writeln!(f, "callbacks:          {:?}", self.callback_time)?;
+        writeln!(f, "denoise:            {:?}", self.denoise_time)?;
+        writeln!(f, "post:               {:?}", self.post_time)?;
+        writeln!(f, "quantize:           {:?}", self.quantize_time)?;
+        write!(f, "total:              {:?}", self.total_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_image() {
+        let progressive = Progressive {
+            tiling: Tiling::Tiles(4),
+            passes: vec![2, 1],
+        };
+        let tiles = progressive.tiles(10, 5, 1);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles.iter().map(|t| t.width * t.height).sum::<u32>(), 50);
+        assert_eq!(tiles[5], Tile { x: 8, y: 4, width: 2, height: 1, pass: 1, block_size: 1 });
+
+        assert_eq!(Progressive::scanlines().tiles(10, 5, 0).len(), 5);
+    }
+
+    #[test]
+    fn pixel_estimate() {
+        let mut flat = PixelEstimate::default();
+        let mut noisy = PixelEstimate::default();
+        for i in 0..8 {
+            flat.add(0.5);
+            noisy.add((i % 2) as f64);
+        }
+        assert_eq!(flat.error(), 0.);
+        assert!((noisy.error() - (1f64 / 28.).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tile_rays() {
+        let tiles = Progressive::default().tiles(40, 40, 0);
+        assert_eq!(tiles.iter().map(Tile::rays).sum::<u64>(), 25);
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt::Debug;
+
+/// Source of sample values for every stochastic effect of the renderer.
+///
+/// A value only depends on the pixel, the index of the sample inside the
+/// pixel and the dimension being drawn (each random decision of a path uses
+/// the next dimension), so renders are reproducible no matter in which order
+/// or on which thread pixels are traced.
+pub trait Sampler: Debug + Send + Sync {
+    /// Value in `[0, 1)`.
+    fn sample_1d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> f64;
+
+    /// Point in `[0, 1)²` using dimensions `dimension` and `dimension + 1`.
+    /// Samplers that stratify in 2D override this.
+    fn sample_2d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> (f64, f64) {
+        (
+            self.sample_1d(pixel, index, dimension),
+            self.sample_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+/// Independent uniform random values.
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    pub seed: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn sample_1d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> f64 {
+        to_unit(hash(&[self.seed, pixel_key(pixel), index as u64, dimension as u64]))
+    }
+}
+
+/// Splits the pixel into `x_strata * y_strata` cells and places one sample in
+/// each, visiting the cells in a different random order per dimension.
+/// Samples past the cell count start another round over the cells.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    pub seed: u64,
+    pub x_strata: u32,
+    pub y_strata: u32,
+    /// Random position inside the cell instead of its center.
+    pub jitter: bool,
+}
+
+impl StratifiedSampler {
+    /// Strata for `samples` samples per pixel, as close to square as possible.
+    pub fn new(seed: u64, samples: u32) -> Self {
+        let samples = samples.max(1);
+        let mut x_strata = (samples as f64).sqrt() as u32;
+        while samples % x_strata != 0 {
+            x_strata -= 1;
+        }
+        Self {
+            seed,
+            x_strata,
+            y_strata: samples / x_strata,
+            jitter: true,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        (self.x_strata * self.y_strata).max(1)
+    }
+
+    fn offset(&self, key: u64) -> f64 {
+        if self.jitter { to_unit(key) } else { 0.5 }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample_1d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> f64 {
+        let n = self.count();
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64, (index / n) as u64]);
+        let stratum = permute(index % n, n, key as u32);
+        (stratum as f64 + self.offset(hash(&[key, index as u64]))) / n as f64
+    }
+
+    fn sample_2d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> (f64, f64) {
+        let n = self.count();
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64, (index / n) as u64]);
+        let stratum = permute(index % n, n, key as u32);
+        let (sx, sy) = (stratum % self.x_strata, stratum / self.x_strata);
+        (
+            (sx as f64 + self.offset(hash(&[key, index as u64, 0]))) / self.x_strata as f64,
+            (sy as f64 + self.offset(hash(&[key, index as u64, 1]))) / self.y_strata as f64,
+        )
+    }
+}
+
+/// Halton low-discrepancy sequence, one prime base per dimension, randomly
+/// shifted per pixel (Cranley-Patterson rotation) to decorrelate pixels.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    pub seed: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+impl Sampler for HaltonSampler {
+    fn sample_1d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> f64 {
+        let shift = to_unit(hash(&[self.seed, pixel_key(pixel), dimension as u64]));
+        // high dimensions of Halton correlate badly; past the table the
+        // values are plain random
+        let v = match PRIMES.get(dimension as usize) {
+            Some(&base) => radical_inverse(base, index),
+            None => to_unit(hash(&[self.seed, pixel_key(pixel), index as u64, dimension as u64, 1])),
+        };
+        (v + shift).fract()
+    }
+}
+
+/// Sobol (0, 2)-sequence, padded to any number of dimensions: every pair of
+/// dimensions uses the first two Sobol dimensions with its own random index
+/// shuffle and digit scrambling.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    pub seed: u64,
+    /// Samples per pixel, rounded up to a power of two. Indices are shuffled
+    /// inside blocks of this size.
+    pub samples: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, samples: u32) -> Self {
+        Self {
+            seed,
+            samples: samples.max(1).next_power_of_two(),
+        }
+    }
+
+    fn shuffled_index(&self, key: u64, index: u32) -> u32 {
+        let n = self.samples;
+        (index / n) * n + permute(index % n, n, key as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn sample_1d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> f64 {
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64]);
+        let i = self.shuffled_index(key, index);
+        to_unit_u32(i.reverse_bits() ^ (key >> 32) as u32)
+    }
+
+    fn sample_2d(&self, pixel: (i32, i32), index: u32, dimension: u32) -> (f64, f64) {
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64, 2]);
+        let i = self.shuffled_index(key, index);
+        let scramble = hash(&[key]);
+        (
+            to_unit_u32(i.reverse_bits() ^ scramble as u32),
+            to_unit_u32(sobol_dimension_1(i) ^ (scramble >> 32) as u32),
+        )
+    }
+}
+
+/// Hands out consecutive dimensions of one sample of one pixel.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SampleStream<'a> {
+    sampler: &'a dyn Sampler,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: u32,
+    /// Most reflections in a row the path of this sample went through, for
+    /// [`RenderStats`](crate::RenderStats).
+    pub deepest: u32,
+}
+
+impl<'a> SampleStream<'a> {
+    pub fn new(sampler: &'a dyn Sampler, pixel: (i32, i32), index: u32) -> Self {
+        Self {
+            sampler, pixel, index,
+            dimension: 0,
+            deepest: 0,
+        }
+    }
+
+    pub fn next_2d(&mut self) -> (f64, f64) {
+        let v = self.sampler.sample_2d(self.pixel, self.index, self.dimension);
+        self.dimension += 2;
+        v
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv = inv_base;
+    let mut reversed = 0.;
+    while index > 0 {
+        reversed += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    reversed.min(1. - f64::EPSILON)
+}
+
+/// Second Sobol dimension, generated by the polynomial `x + 1`.
+fn sobol_dimension_1(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Stateless permutation of `0..n` chosen by `key` (Kensler, "Correlated
+/// Multi-Jittered Sampling").
+fn permute(mut i: u32, n: u32, key: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= key;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= key >> 16;
+        i ^= (i & w) >> 4;
+        i ^= key >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= key >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | key >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(key)) % n
+}
+
+fn pixel_key((x, y): (i32, i32)) -> u64 {
+    ((x as u32 as u64) << 32) | y as u32 as u64
+}
+
+/// Mixes the values into a well distributed 64-bit hash (splitmix64 steps).
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    let mut h = 0x9e3779b97f4a7c15u64;
+    for &v in values {
+        h ^= v;
+        h = h.wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+    }
+    h
+}
+
+pub(crate) fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn to_unit_u32(v: u32) -> f64 {
+    v as f64 / (u32::MAX as f64 + 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permutation() {
+        for n in [1, 5, 16, 37] {
+            let mut seen: Vec<u32> = (0..n).map(|i| permute(i, n, 0xdeadbeef)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..n).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn stratified_covers_cells() {
+        let sampler = StratifiedSampler::new(7, 16);
+        assert_eq!((sampler.x_strata, sampler.y_strata), (4, 4));
+
+        let mut cells: Vec<(u32, u32)> = (0..16)
+            .map(|i| sampler.sample_2d((3, -2), i, 0))
+            .map(|(u, v)| ((u * 4.) as u32, (v * 4.) as u32))
+            .collect();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 16);
+    }
+
+    #[test]
+    fn sobol_is_stratified() {
+        let sampler = SobolSampler::new(3, 16);
+        let mut cells: Vec<(u32, u32)> = (0..16)
+            .map(|i| sampler.sample_2d((0, 0), i, 4))
+            .map(|(u, v)| ((u * 4.) as u32, (v * 4.) as u32))
+            .collect();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 16);
+    }
+
+    #[test]
+    fn deterministic() {
+        let samplers: [&dyn Sampler; 4] = [
+            &IndependentSampler::new(1),
+            &StratifiedSampler::new(1, 4),
+            &HaltonSampler::new(1),
+            &SobolSampler::new(1, 4),
+        ];
+        for sampler in samplers {
+            let v = sampler.sample_2d((10, 20), 3, 2);
+            assert_eq!(v, sampler.sample_2d((10, 20), 3, 2));
+            assert_ne!(v, sampler.sample_2d((11, 20), 3, 2));
+            assert!((0. ..1.).contains(&v.0) && (0. ..1.).contains(&v.1));
+        }
+    }
+}
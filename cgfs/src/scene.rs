@@ -1,17 +1,107 @@
-use cgmath::{prelude::*, Point3, Vector3};
-use crate::color::Color;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use cgmath::{prelude::*, Point3, Vector3, vec3 as vector};
+use crate::bounds::Aabb;
+use crate::material::Material;
+use crate::ray::RayKind;
+use crate::transform::Transform;
 
 #[derive(Debug, Clone)]
 pub struct Sphere {
+    /// Used by [`LightLinks`]; may be empty.
+    pub name: String,
     pub pos: Point3<f64>,
     pub radius: f64,
-    pub color: Color,
-    pub specular: f64,
+    pub material: Material,
+
+    /// Placement of the sphere on top of `pos`, e.g. to squash it into an
+    /// ellipsoid. `pos` and `radius` are in object space.
+    pub transform: Transform,
+    /// Which rays see the sphere. Hiding it from shadow rays stops it from
+    /// casting shadows, but not from lighting others if it is emissive.
+    pub visibility: Visibility,
+    /// Whether other objects shadow the sphere.
+    pub receive_shadows: bool,
+    /// Objects lit by the sphere's emission, like the links of a [`Light`].
+    pub emission_links: LightLinks,
+}
+
+/// Which kinds of rays see an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub reflections: bool,
+    pub shadows: bool,
+}
 
-    pub reflective: f64,
+impl Visibility {
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Reflection => self.reflections,
+            RayKind::Shadow | RayKind::Emission => self.shadows,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self {
+            camera: true,
+            reflections: true,
+            shadows: true,
+        }
+    }
 }
 
 impl Sphere {
+    /// Unnamed sphere seen by every ray, without a transform or links.
+    pub fn new(pos: Point3<f64>, radius: f64, material: Material) -> Self {
+        Self {
+            name: String::new(),
+            pos, radius, material,
+            transform: Transform::default(),
+            visibility: Visibility::default(),
+            receive_shadows: true,
+            emission_links: LightLinks::default(),
+        }
+    }
+
+    /// Whether rays of `kind` see the sphere, see [`RayKind::Emission`].
+    pub fn is_visible_to(&self, kind: RayKind) -> bool {
+        self.visibility.sees(kind) || (kind == RayKind::Emission && self.material.is_emissive())
+    }
+
+    /// Intersects a world space ray, returning both ray parameters.
+    pub fn intersect(&self, origin: Point3<f64>, ray: Vector3<f64>) -> Option<(f64, f64)> {
+        let inverse = self.transform.inverse();
+        self.intersect_ray(inverse.point(origin), inverse.vector(ray))
+    }
+
+    /// World space unit normal at a world space point on the surface.
+    pub fn normal_at(&self, p: Point3<f64>) -> Vector3<f64> {
+        let local = self.transform.inverse().point(p);
+        self.transform.normal(local - self.pos).normalize()
+    }
+
+    /// Texture coordinates of a world space point on the surface: `u` goes
+    /// around the y axis of the sphere, `v` from its top (`0`) to its bottom
+    /// (`1`).
+    pub fn uv_at(&self, p: Point3<f64>) -> (f64, f64) {
+        let d = (self.transform.inverse().point(p) - self.pos).normalize();
+        let u = 0.5 + d.x.atan2(d.z) / (2. * PI);
+        let v = d.y.clamp(-1., 1.).acos() / PI;
+        (u, v)
+    }
+
+    /// Bounding box in world space (or in the space of the enclosing group).
+    pub fn bounds(&self) -> Aabb {
+        let r = vector(self.radius, self.radius, self.radius);
+        Aabb::new(self.pos - r, self.pos + r).transformed(&self.transform)
+    }
+
+    /// Intersects a ray given in object space.
     pub fn intersect_ray(&self, origin: Point3<f64>, ray: Vector3<f64>) -> Option<(f64, f64)> {
         let co = origin - self.pos;
         let a = ray.dot(ray);
@@ -21,7 +111,7 @@ impl Sphere {
         let delta = b * b - 4. * a * c;
 
         if delta < 0. {
-            return None;
+            None
         } else {
             let sqrt = delta.sqrt();
             let t1 = (-b + sqrt) / (2. * a);
@@ -32,18 +122,409 @@ impl Sphere {
     }
 }
 
+/// Named node of the scene hierarchy. Its children are placed relative to
+/// the group, so moving the group moves all of them. The children are fixed
+/// once the group is built, as their bounds are cached.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub name: String,
+    transform: Transform,
+    spheres: Vec<Sphere>,
+    groups: Vec<Group>,
+    instances: Vec<Instance>,
+    /// Children bounds in the group's own space.
+    bounds: Aabb,
+    object_count: usize,
+    emissive: bool,
+}
+
+impl Group {
+    pub fn new(name: String, transform: Transform, spheres: Vec<Sphere>, groups: Vec<Group>, instances: Vec<Instance>) -> Self {
+        let mut group = Self {
+            name, transform, spheres, groups, instances,
+            bounds: Aabb::EMPTY,
+            object_count: 0,
+            emissive: false,
+        };
+        group.update();
+        group
+    }
+
+    /// Computes the cached bounds, object count and emission of the children.
+    fn update(&mut self) {
+        self.bounds = self.spheres.iter().map(Sphere::bounds)
+            .chain(self.groups.iter().map(Group::bounds))
+            .chain(self.instances.iter().map(Instance::bounds))
+            .fold(Aabb::EMPTY, |a, b| a.union(&b));
+        self.object_count = object_count(&self.spheres, &self.groups, &self.instances);
+        self.emissive = any_emissive(&self.spheres, &self.groups, &self.instances);
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// Moves the group. The cached bounds are in the group's own space, so
+    /// they stay valid.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    pub fn spheres(&self) -> &[Sphere] {
+        &self.spheres
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// Whether any sphere inside has an emissive material.
+    pub fn is_emissive(&self) -> bool {
+        self.emissive
+    }
+
+    /// Bounding box in the space of the parent.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds.transformed(&self.transform)
+    }
+
+    /// Number of spheres in the group, its subgroups and its instances.
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// Sphere by index, counting the group's own spheres first, then each
+    /// subgroup in order and finally each instance.
+    pub fn object(&self, index: usize) -> Option<&Sphere> {
+        object(&self.spheres, &self.groups, &self.instances, index)
+    }
+}
+
+/// Placement of a shared piece of geometry. Many instances can point to the
+/// same geometry, which is stored only once.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    /// Used by [`LightLinks`]; may be empty.
+    pub name: String,
+    pub geometry: Arc<Group>,
+    /// Applied on top of the geometry's own transform.
+    pub transform: Transform,
+    /// Replaces the whole material of every sphere of the geometry, not
+    /// just some of its fields.
+    pub material: Option<Material>,
+}
+
+impl Instance {
+    pub fn new(geometry: Arc<Group>, transform: Transform) -> Self {
+        Self {
+            name: String::new(),
+            geometry, transform,
+            material: None,
+        }
+    }
+
+    /// Bounding box in the space of the parent.
+    pub fn bounds(&self) -> Aabb {
+        self.geometry.bounds().transformed(&self.transform)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        match &self.material {
+            Some(material) => material.is_emissive(),
+            None => self.geometry.is_emissive(),
+        }
+    }
+}
+
+pub(crate) fn any_emissive(spheres: &[Sphere], groups: &[Group], instances: &[Instance]) -> bool {
+    spheres.iter().any(|s| s.material.is_emissive())
+        || groups.iter().any(Group::is_emissive)
+        || instances.iter().any(Instance::is_emissive)
+}
+
+pub(crate) fn object_count(spheres: &[Sphere], groups: &[Group], instances: &[Instance]) -> usize {
+    spheres.len()
+        + groups.iter().map(|g| g.object_count).sum::<usize>()
+        + instances.iter().map(|i| i.geometry.object_count).sum::<usize>()
+}
+
+pub(crate) fn object<'a>(spheres: &'a [Sphere], groups: &'a [Group], instances: &'a [Instance], index: usize) -> Option<&'a Sphere> {
+    object_path(spheres, groups, instances, index, &mut Vec::new())
+}
+
+/// [`object`], also adding the names of the instances and groups around the
+/// sphere to `path`, outermost first.
+pub(crate) fn object_path<'a>(
+    spheres: &'a [Sphere],
+    groups: &'a [Group],
+    instances: &'a [Instance],
+    mut index: usize,
+    path: &mut Vec<&'a str>,
+) -> Option<&'a Sphere> {
+    if let Some(sphere) = spheres.get(index) {
+        return Some(sphere);
+    }
+    index -= spheres.len();
+    let nodes = groups.iter().map(|g| (None, g))
+        .chain(instances.iter().map(|i| (Some(i.name.as_str()), i.geometry.as_ref())));
+    for (instance, group) in nodes {
+        if index < group.object_count {
+            path.extend(instance);
+            path.push(&group.name);
+            return object_path(&group.spheres, &group.groups, &group.instances, index, path);
+        }
+        index -= group.object_count;
+    }
+    None
+}
+
+/// Closest hit inside one coordinate space.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LocalHit<'a> {
+    pub t: f64,
+    pub object: usize,
+    pub sphere: &'a Sphere,
+    /// Outermost instance overriding the sphere's material.
+    pub instance: Option<&'a Instance>,
+    /// Unit normal in the space the ray was given in.
+    pub normal: Vector3<f64>,
+    pub uv: (f64, f64),
+}
+
+/// Closest intersection with `spheres` and the contents of `groups` and
+/// `instances`, which are numbered from `first_index` on. Spheres hidden from
+/// `kind` are ignored and groups whose bounds the ray misses are skipped.
+/// Adds the number of tests made to `tests`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn closest_hit<'a>(
+    spheres: &'a [Sphere],
+    groups: &'a [Group],
+    instances: &'a [Instance],
+    first_index: usize,
+    origin: Point3<f64>,
+    ray: Vector3<f64>,
+    t_bounds: (f64, f64),
+    kind: RayKind,
+    tests: &mut u64,
+) -> Option<LocalHit<'a>> {
+    let mut closest_t = f64::INFINITY;
+    let mut closest_sphere = None;
+
+    *tests += spheres.len() as u64;
+    for (i, sphere) in spheres.iter().enumerate() {
+        if !sphere.is_visible_to(kind) {
+            continue;
+        }
+        let (t1, t2) = match sphere.intersect(origin, ray) {
+            Some(t) => t,
+            None => continue,
+        };
+
+        if t1 > t_bounds.0 && t1 < t_bounds.1 && t1 < closest_t {
+            closest_t = t1;
+            closest_sphere = Some(i);
+        }
+        if t2 > t_bounds.0 && t2 < t_bounds.1 && t2 < closest_t {
+            closest_t = t2;
+            closest_sphere = Some(i);
+        }
+    }
+    let mut closest = closest_sphere.map(|i| {
+        let p = origin + ray * closest_t;
+        LocalHit {
+            t: closest_t,
+            object: first_index + i,
+            sphere: &spheres[i],
+            instance: None,
+            normal: spheres[i].normal_at(p),
+            uv: spheres[i].uv_at(p),
+        }
+    });
+
+    let mut index = first_index + spheres.len();
+    for group in groups {
+        let bounds = (t_bounds.0, t_bounds.1.min(closest_t));
+        if let Some(hit) = group_hit(group, index, origin, ray, bounds, kind, tests) {
+            closest_t = hit.t;
+            closest = Some(hit);
+        }
+        index += group.object_count;
+    }
+
+    for instance in instances {
+        let inverse = instance.transform.inverse();
+        let bounds = (t_bounds.0, t_bounds.1.min(closest_t));
+        let hit = group_hit(&instance.geometry, index, inverse.point(origin), inverse.vector(ray), bounds, kind, tests);
+
+        if let Some(hit) = hit {
+            closest_t = hit.t;
+            closest = Some(LocalHit {
+                normal: instance.transform.normal(hit.normal).normalize(),
+                instance: Some(instance).filter(|i| i.material.is_some()).or(hit.instance),
+                ..hit
+            });
+        }
+        index += instance.geometry.object_count;
+    }
+    closest
+}
+
+/// [`closest_hit`] for the contents of a group, with the ray given in the
+/// space of the group's parent.
+fn group_hit<'a>(
+    group: &'a Group,
+    first_index: usize,
+    origin: Point3<f64>,
+    ray: Vector3<f64>,
+    t_bounds: (f64, f64),
+    kind: RayKind,
+    tests: &mut u64,
+) -> Option<LocalHit<'a>> {
+    let inverse = group.transform.inverse();
+    let (origin, ray) = (inverse.point(origin), inverse.vector(ray));
+
+    *tests += 1;
+    if !group.bounds.intersects(origin, ray, t_bounds) {
+        return None;
+    }
+    closest_hit(&group.spheres, &group.groups, &group.instances, first_index, origin, ray, t_bounds, kind, tests)
+        .map(|hit| LocalHit {
+            normal: group.transform.normal(hit.normal).normalize(),
+            ..hit
+        })
+}
+
 #[derive(Debug, Clone)]
 pub enum Light {
     Ambient {
         intensity: f64,
+        links: LightLinks,
     },
     Directional {
         intensity: f64,
         direction: Vector3<f64>,
+        /// Whether objects block the light.
+        shadows: bool,
+        links: LightLinks,
     },
     Point {
         intensity: f64,
         pos: Point3<f64>,
+        /// Whether objects block the light.
+        shadows: bool,
+        links: LightLinks,
     },
 }
 
+impl Light {
+    pub fn links(&self) -> &LightLinks {
+        match self {
+            Light::Ambient { links, .. } | Light::Directional { links, .. } | Light::Point { links, .. } => links,
+        }
+    }
+}
+
+/// Restricts a light to some objects by name. An object goes by its own name
+/// and those of the groups and instances around it; one with no names at all
+/// is lit by every light.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LightLinks {
+    /// If not empty, only these objects are lit.
+    pub include: Vec<String>,
+    /// These objects are never lit.
+    pub exclude: Vec<String>,
+}
+
+impl LightLinks {
+    pub fn lights(&self, name: &str) -> bool {
+        self.lights_path(&[name])
+    }
+
+    /// Whether the object with the names `path` is lit. Any one of the names
+    /// can include or exclude it, and empty ones are skipped.
+    pub fn lights_path(&self, path: &[&str]) -> bool {
+        let names = || path.iter().filter(|n| !n.is_empty());
+        if names().next().is_none() {
+            return true;
+        }
+        let listed = |list: &[String]| names().any(|n| list.iter().any(|l| l == n));
+        (self.include.is_empty() || listed(&self.include)) && !listed(&self.exclude)
+    }
+
+    /// Whether the links leave every object lit.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_from_ray_kind() {
+        let sphere = |z: f64, visibility| Sphere {
+            name: String::new(),
+            pos: Point3::new(0., 0., z),
+            radius: 1.,
+            material: Material::default(),
+            transform: Transform::identity(),
+            visibility,
+            receive_shadows: true,
+            emission_links: LightLinks::default(),
+        };
+        let spheres = [
+            sphere(5., Visibility { shadows: false, ..Default::default() }),
+            sphere(10., Visibility::default()),
+        ];
+        let hit = |kind| {
+            closest_hit(&spheres, &[], &[], 0, Point3::new(0., 0., 0.), vector(0., 0., 1.), (0., f64::INFINITY), kind, &mut 0)
+                .map(|h| h.object)
+        };
+        assert_eq!(hit(RayKind::Camera), Some(0));
+        assert_eq!(hit(RayKind::Shadow), Some(1));
+    }
+
+    #[test]
+    fn moved_group_bounds() {
+        let sphere = Sphere {
+            name: String::new(),
+            pos: Point3::new(0., 0., 5.),
+            radius: 1.,
+            material: Material::default(),
+            transform: Transform::identity(),
+            visibility: Visibility::default(),
+            receive_shadows: true,
+            emission_links: LightLinks::default(),
+        };
+        let inner = Group::new("inner".into(), Transform::identity(), vec![sphere], vec![], vec![]);
+        let mut outer = Group::new("outer".into(), Transform::identity(), vec![], vec![inner], vec![]);
+        assert_eq!(outer.bounds().min, Point3::new(-1., -1., 4.));
+
+        outer.set_transform(Transform::translate(vector(2., 0., 0.)));
+        assert_eq!(outer.bounds().min, Point3::new(1., -1., 4.));
+        assert_eq!(outer.object_count(), 1);
+    }
+
+    #[test]
+    fn light_links() {
+        let links = LightLinks {
+            include: vec!["hero".into(), "prop".into()],
+            exclude: vec!["prop".into()],
+        };
+        assert!(links.lights("hero"));
+        assert!(!links.lights("prop"));
+        assert!(!links.lights("floor"));
+        assert!(links.lights(""));
+        assert!(LightLinks::default().lights("floor"));
+        // by the name of an enclosing group or instance
+        assert!(links.lights_path(&["hero", "", "floor"]));
+        assert!(!links.lights_path(&["hero", "prop"]));
+        assert!(links.lights_path(&["", ""]));
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use image::Rgb32FImage;
+
+use crate::color::FloatColor;
+
+/// Image looked up by `(u, v)` coordinates in `[0, 1]`, with `v = 0` at the
+/// top row. `u` wraps around, `v` is clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    image: Rgb32FImage,
+}
+
+impl Texture {
+    /// Fails if the image has no texels.
+    pub fn new(image: Rgb32FImage) -> Result<Self, String> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err("empty image".into());
+        }
+        Ok(Self { image })
+    }
+
+    /// Loads any image format the crate was built with. 8-bit images are
+    /// mapped to `[0, 1]` without any gamma conversion, like [`Color`](crate::Color).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|e| format!("could not open `{}`: {e}", path.display()))?;
+        Self::new(image.to_rgb32f())
+    }
+
+    pub fn image(&self) -> &Rgb32FImage {
+        &self.image
+    }
+
+    /// Bilinearly filtered value at `(u, v)`.
+    pub fn sample(&self, (u, v): (f64, f64)) -> FloatColor {
+        let (w, h) = self.image.dimensions();
+        let x = u.rem_euclid(1.) * w as f64 - 0.5;
+        let y = v.clamp(0., 1.) * h as f64 - 0.5;
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+
+        let texel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(w as i64) as u32;
+            let y = (y.max(0.) as u32).min(h - 1);
+            FloatColor::from(self.image.get_pixel(x, y).0)
+        };
+        let (x, y) = (x.floor(), y.floor());
+        let top = texel(x, y) * (1. - fx) + texel(x + 1., y) * fx;
+        let bottom = texel(x, y + 1.) * (1. - fx) + texel(x + 1., y + 1.) * fx;
+        top * (1. - fy) + bottom * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn bilinear() {
+        let image = Rgb32FImage::from_fn(2, 1, |x, _| Rgb([x as f32; 3]));
+        let tex = Texture::new(image).unwrap();
+        assert_eq!(tex.sample((0.25, 0.5)).r, 0.);
+        assert_eq!(tex.sample((0.5, 0.5)).r, 0.5);
+        // wraps around between the last and the first texel
+        assert_eq!(tex.sample((0., 0.5)).r, 0.5);
+
+        assert_eq!(Texture::new(Rgb32FImage::new(2, 0)).unwrap_err(), "empty image");
+    }
+}
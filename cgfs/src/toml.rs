@@ -1,15 +1,25 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use toml::Value;
 use toml::value::Table;
 
 use crate::{
     Camera, Viewport, Canvas,
     RayTracer, Point, Vector,
-    Color,
-    Sphere, Light,
-    color, point,
+    Color, FloatColor,
+    Sphere, Light, LightLinks, Group, Instance, Visibility, Material, ShadingModel, Texture,
+    Environment, Aov, AovBuffers,
+    Sampling, Adaptive,
+    PostProcess, PostStage, Denoiser,
+    Quantizer, Dither, Palette,
+    Sampler, IndependentSampler, StratifiedSampler, HaltonSampler, SobolSampler,
+    Transform,
+    color, point, vector,
 };
 
-fn get_table<'a>(toml: &'a Value) -> Result<&'a Table, String> {
+fn get_table(toml: &Value) -> Result<&Table, String> {
     match toml.as_table() {
         Some(t) => Ok(t),
         None => Err("expected `table`".into())
@@ -34,6 +44,99 @@ fn ret_obj<T, F: FnOnce() -> Result<T, String>>(err: &str, f: F) -> Result<T, St
     f().map_err(|e| format!("{err}:\n{e}"))
 }
 
+/// Like `table_get_default` for an array of tables, building every element
+/// with `f`.
+fn table_get_list<T, F>(table: &Table, key: &str, mut f: F) -> Result<Vec<T>, String>
+where
+    F: FnMut(&Value) -> Result<T, String>,
+{
+    let values = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(Value::Array(v)) => v,
+        Some(_) => return Err(format!("expected array at `{key}`")),
+    };
+    let mut out = Vec::with_capacity(values.len());
+    for (i, value) in values.iter().enumerate() {
+        out.push(f(value).map_err(|e| format!("error in array definition:\n{e} at index `{i}` at `{key}`"))?);
+    }
+    Ok(out)
+}
+
+/// Named definitions objects can refer to: the `[materials]` table, and the
+/// `[geometries]` table. Each geometry is built the first time an instance
+/// refers to it, so every instance of a geometry shares the same `Arc`.
+#[derive(Default)]
+struct Library<'a> {
+    materials: HashMap<String, Material>,
+    table: Option<&'a Table>,
+    built: RefCell<HashMap<String, Arc<Group>>>,
+    /// Definitions being built, to catch geometries that contain themselves.
+    building: RefCell<Vec<String>>,
+}
+
+impl<'a> Library<'a> {
+    fn new(root: &'a Table) -> Result<Self, String> {
+        let mut materials = HashMap::new();
+        if let Some(toml) = root.get("materials") {
+            for (name, material) in get_table(toml)? {
+                let material = Material::from_toml(material)
+                    .map_err(|e| format!("{e} at `{name}`"))?;
+                materials.insert(name.clone(), material);
+            }
+        }
+        Ok(Self {
+            materials,
+            table: root.get("geometries").map(get_table).transpose()?,
+            ..Default::default()
+        })
+    }
+
+    fn material(&self, name: &str) -> Result<Material, String> {
+        self.materials.get(name)
+            .cloned()
+            .ok_or_else(|| format!("unknown material `{name}`"))
+    }
+
+    /// The `material` key of an object, either the name of a library
+    /// material or an inline table, or else a material given by the object's
+    /// own `color`, `specular` and `reflective` keys. `None` if the object
+    /// has neither.
+    fn object_material(&self, toml: &Value) -> Result<Option<Material>, String> {
+        let table = get_table(toml)?;
+        match table.get("material") {
+            Some(Value::String(name)) => self.material(name).map(Some),
+            Some(material) => Material::from_toml(material).map(Some),
+            None if table.contains_key("color") => Material::from_toml(toml).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn geometry(&self, name: &str) -> Result<Arc<Group>, String> {
+        if let Some(geometry) = self.built.borrow().get(name) {
+            return Ok(geometry.clone());
+        }
+        let toml = self.table
+            .and_then(|t| t.get(name))
+            .ok_or_else(|| format!("unknown geometry `{name}`"))?;
+        if self.building.borrow().iter().any(|n| n == name) {
+            return Err(format!("geometry `{name}` contains itself"));
+        }
+
+        self.building.borrow_mut().push(name.to_owned());
+        let group = group_from_toml(toml, self)
+            .map_err(|e| format!("error in geometry `{name}`:\n{e}"));
+        self.building.borrow_mut().pop();
+
+        let mut group = group?;
+        if group.name.is_empty() {
+            group.name = name.to_owned();
+        }
+        let geometry = Arc::new(group);
+        self.built.borrow_mut().insert(name.to_owned(), geometry.clone());
+        Ok(geometry)
+    }
+}
+
 
 pub trait FromToml where Self: Sized {
     fn from_toml(toml: &Value) -> Result<Self, String>;
@@ -47,29 +150,49 @@ impl FromToml for RayTracer {
         };
         let canvas: Canvas = table_get(table, "canvas")?;
         let camera: Camera = table_get(table, "camera")?;
-        let viewport = table_get_default(table, "viewport", {
-            let ratio = canvas.width() as f64 / canvas.height() as f64;
-            let width = 1.;
-            let height = width / ratio;
-            Viewport {
-                width, height,
-                distance: 1.,
-            }
-        })?;
-        let background = table_get_default(table, "background", color(0,0,0))?;
-        let spheres = table_get_default(table, "spheres", Vec::new())?;
+        let viewport = table_get_default(table, "viewport", Viewport::fit(&canvas))?;
+        let background = match table.get("background") {
+            Some(Value::String(s)) if s == "transparent" => None,
+            _ => Some(table_get_default(table, "background", color(0,0,0))?),
+        };
+        let lib = Library::new(table)?;
+        let spheres = table_get_list(table, "spheres", |v| sphere_from_toml(v, &lib))?;
+        let groups = table_get_list(table, "groups", |v| group_from_toml(v, &lib))?;
+        let instances = table_get_list(table, "instances", |v| instance_from_toml(v, &lib))?;
         let lights = table_get_default(table, "lights", Vec::new())?;
+        let environment = table_get_default(table, "environment", None)?;
 
         let recursion_depth = table_get_default(table, "recursion", 3)?;
-        
+
+        let mut aovs = AovBuffers::default();
+        for aov in table_get_default(table, "aovs", Vec::<Aov>::new())? {
+            aovs.enable(aov, canvas.width(), canvas.height());
+        }
+
+        let sampling = table_get_default(table, "sampling", Sampling::default())?;
+        let denoise = table_get_default(table, "denoise", None)?;
+        let post = table_get_default(table, "post", PostProcess::default())?;
+        let quantize = table_get_default(table, "quantize", Quantizer::default())?;
+        let stats = table_get_default(table, "stats", false)?
+            .then(Default::default);
+
         Ok(RayTracer {
             canvas,
             camera,
             viewport,
             background,
             spheres,
+            groups,
+            instances,
             lights,
-            recursion_depth
+            environment,
+            recursion_depth,
+            aovs,
+            sampling,
+            denoise,
+            post,
+            quantize,
+            stats,
         })
     }
 }
@@ -125,28 +248,164 @@ impl FromToml for Viewport {
     }
 }
 
+/// A sphere on its own can't refer to library materials.
 impl FromToml for Sphere {
     fn from_toml(toml: &Value) -> Result<Self, String> {
-        let err = "error in `sphere` definition";
+        sphere_from_toml(toml, &Library::default())
+    }
+}
+
+fn sphere_from_toml(toml: &Value, lib: &Library) -> Result<Sphere, String> {
+    let err = "error in `sphere` definition";
+
+    ret_obj(err, || {
+        let table = get_table(toml)?;
+
+        let pos = table_get(table, "position")?;
+        let radius = table_get(table, "radius")?;
+        let material = lib.object_material(toml)?
+            .ok_or("missing field `material` or `color`")?;
+        let transform = Transform::from_toml(toml)?;
+        let visibility = Visibility::from_toml(toml)?;
+        let receive_shadows = table_get_default(table, "receive_shadows", true)?;
+        let emission_links = LightLinks::from_toml(toml)?;
+
+        let name = table_get_default(table, "name", String::new())?;
+
+        Ok(Sphere {
+            name,
+            pos, radius, material,
+            transform,
+            visibility,
+            receive_shadows,
+            emission_links,
+        })
+    })
+}
+
+/// A group on its own can't refer to library materials or geometries.
+impl FromToml for Group {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        group_from_toml(toml, &Library::default())
+    }
+}
+
+fn group_from_toml(toml: &Value, lib: &Library) -> Result<Group, String> {
+    let err = "error in `group` definition";
+
+    ret_obj(err, || {
+        let table = get_table(toml)?;
+
+        let name = table_get_default(table, "name", String::new())?;
+        let transform = Transform::from_toml(toml)?;
+        let spheres = table_get_list(table, "spheres", |v| sphere_from_toml(v, lib))?;
+        let groups = table_get_list(table, "groups", |v| group_from_toml(v, lib))?;
+        let instances = table_get_list(table, "instances", |v| instance_from_toml(v, lib))?;
+
+        Ok(Group::new(name, transform, spheres, groups, instances))
+    })
+}
+
+/// Keys of an inline material other than `color`.
+const MATERIAL_KEYS: [&str; 12] = [
+    "specular", "reflective", "model", "bands", "metallic", "roughness", "reflection_samples",
+    "texture", "metallic_texture", "roughness_texture", "emission", "emission_strength",
+];
+
+/// An instance's material, if it has one, replaces the materials of the
+/// whole geometry. Its `color` is a complete material whose other keys take
+/// their defaults, so material keys without a `color` or `material` are
+/// rejected rather than ignored.
+fn instance_from_toml(toml: &Value, lib: &Library) -> Result<Instance, String> {
+    let err = "error in `instance` definition";
+
+    ret_obj(err, || {
+        let table = get_table(toml)?;
+
+        let name: String = table_get(table, "geometry")?;
+        let geometry = lib.geometry(&name)?;
+        let transform = Transform::from_toml(toml)?;
+        let material = lib.object_material(toml)?;
+        if material.is_none() {
+            if let Some(key) = MATERIAL_KEYS.iter().find(|&&key| table.contains_key(key)) {
+                return Err(format!("`{key}` needs a `color` or `material`, which replaces the geometry's materials"));
+            }
+        }
+
+        Ok(Instance {
+            name: table_get_default(table, "name", String::new())?,
+            material,
+            ..Instance::new(geometry, transform)
+        })
+    })
+}
+
+impl FromToml for Material {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `material` definition";
 
         ret_obj(err, || {
             let table = get_table(toml)?;
 
-            let pos = table_get(table, "position")?;
-            let radius = table_get(table, "radius")?;
             let color = table_get(table, "color")?;
-
             let specular = table_get_default(table, "specular", 0.)?;
             let reflective = table_get_default(table, "reflective", 0.)?;
-
-            Ok(Sphere {
-                pos, radius, color,
-                specular, reflective
+            let model = ShadingModel::from_toml(toml)?;
+            let default = Material::default();
+
+            let texture = |key| -> Result<Option<Arc<Texture>>, String> {
+                let path: Option<String> = table_get_default(table, key, None)?;
+                path.map(|p| Texture::load(p).map(Arc::new).map_err(|e| format!("{e} at `{key}`")))
+                    .transpose()
+            };
+
+            Ok(Material {
+                color, specular, reflective, model,
+                metallic: table_get_default(table, "metallic", default.metallic)?,
+                roughness: table_get_default(table, "roughness", default.roughness)?,
+                reflection_samples: table_get_default(table, "reflection_samples", default.reflection_samples)?,
+                color_texture: texture("texture")?,
+                metallic_texture: texture("metallic_texture")?,
+                roughness_texture: texture("roughness_texture")?,
+                emission: FloatColor::from(table_get_default(table, "emission", Color::new(0, 0, 0))?)
+                    * table_get_default(table, "emission_strength", 1.)?,
             })
         })
     }
 }
 
+/// Reads the optional `model` key of a material's table, and `bands` for
+/// toon shading.
+impl FromToml for ShadingModel {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let table = get_table(toml)?;
+
+        let model: String = table_get_default(table, "model", "phong".into())?;
+        match model.as_str() {
+            "lambert" => Ok(ShadingModel::Lambert),
+            "phong" => Ok(ShadingModel::Phong),
+            "blinn" | "blinn-phong" => Ok(ShadingModel::BlinnPhong),
+            "pbr" => Ok(ShadingModel::Pbr),
+            "toon" => Ok(ShadingModel::Toon {
+                bands: table_get_default(table, "bands", 3)?,
+            }),
+            _ => Err(format!("unknown shading model `{model}`")),
+        }
+    }
+}
+
+/// The `include` and `exclude` keys of a light or of anything that gives
+/// off light.
+impl FromToml for LightLinks {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let table = get_table(toml)?;
+        Ok(LightLinks {
+            include: table_get_default(table, "include", Vec::new())?,
+            exclude: table_get_default(table, "exclude", Vec::new())?,
+        })
+    }
+}
+
 impl FromToml for Light {
     fn from_toml(toml: &Value) -> Result<Self, String> {
         let err = "error in `light` definition";
@@ -156,23 +415,30 @@ impl FromToml for Light {
 
             let light_type: String = table_get(table, "type")?;
             let intensity = table_get(table, "intensity")?;
+            let shadows = table_get_default(table, "shadows", true)?;
+            let links = LightLinks::from_toml(toml)?;
 
             match light_type.as_str() {
                 "ambient" => Ok(Light::Ambient {
-                    intensity
+                    intensity,
+                    links,
                 }),
                 "point" => {
                     let pos = table_get(table, "position")?;
                     Ok(Light::Point {
                         intensity,
-                        pos
+                        pos,
+                        shadows,
+                        links,
                     })
                 },
                 "directional" => {
                     let direction = table_get(table, "direction")?;
                     Ok(Light::Directional {
                         intensity,
-                        direction
+                        direction,
+                        shadows,
+                        links,
                     })
                 }
                 _ => Err("unknown type".into())
@@ -181,25 +447,262 @@ impl FromToml for Light {
     }
 }
 
+/// Reads the optional `translate`, `rotate` (degrees) and `scale` keys of an
+/// object's table.
+impl FromToml for Transform {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let table = get_table(toml)?;
+
+        let translate = table_get_default(table, "translate", vector(0., 0., 0.))?;
+        let rotate = table_get_default(table, "rotate", vector(0., 0., 0.))?;
+        let scale = match table.get("scale") {
+            Some(Value::Float(_) | Value::Integer(_)) => {
+                let s = table_get(table, "scale")?;
+                vector(s, s, s)
+            }
+            _ => table_get_default(table, "scale", vector(1., 1., 1.))?,
+        };
+
+        Transform::from_parts(translate, rotate, scale)
+            .ok_or_else(|| "`scale` can't be zero".into())
+    }
+}
+
+/// Reads the optional `visible_to_camera`, `visible_to_reflections` and
+/// `cast_shadows` (or `visible_to_shadows`) keys of an object's table.
+impl FromToml for Visibility {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let table = get_table(toml)?;
+
+        let cast_shadows = table_get_default(table, "cast_shadows", true)?;
+        Ok(Visibility {
+            camera: table_get_default(table, "visible_to_camera", true)?,
+            reflections: table_get_default(table, "visible_to_reflections", true)?,
+            shadows: table_get_default(table, "visible_to_shadows", cast_shadows)?,
+        })
+    }
+}
+
+impl FromToml for Environment {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `environment` definition";
+
+        ret_obj(err, || {
+            let table = get_table(toml)?;
+
+            let path: String = table_get(table, "path")?;
+            let mut env = Environment::load(path)?;
+
+            env.intensity = table_get_default(table, "intensity", env.intensity)?;
+            env.rotation = table_get_default(table, "rotation", env.rotation)?;
+            env.samples = table_get_default(table, "samples", env.samples)?;
+            env.visible = table_get_default(table, "visible", env.visible)?;
+            env.links = LightLinks::from_toml(toml)?;
+            Ok(env)
+        })
+    }
+}
+
+impl FromToml for Sampling {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `sampling` definition";
+
+        ret_obj(err, || {
+            let table = get_table(toml)?;
+            let default = Adaptive::default();
+
+            let samples = table_get_default(table, "samples", 1)?;
+            let adaptive = match table_get_default(table, "adaptive", false)? {
+                false => None,
+                true => Some(Adaptive {
+                    max_samples: table_get_default(table, "max_samples", default.max_samples)?,
+                    threshold: table_get_default(table, "threshold", default.threshold)?,
+                }),
+            };
+
+            let seed = table_get_default(table, "seed", 0)?;
+            let sampler_samples = adaptive.as_ref().map_or(samples, |a| a.max_samples);
+            let sampler_type: String = table_get_default(table, "sampler", "independent".into())?;
+            let sampler: Arc<dyn Sampler> = match sampler_type.as_str() {
+                "independent" => Arc::new(IndependentSampler::new(seed)),
+                "stratified" => Arc::new(StratifiedSampler::new(seed, sampler_samples)),
+                "halton" => Arc::new(HaltonSampler::new(seed)),
+                "sobol" => Arc::new(SobolSampler::new(seed, sampler_samples)),
+                _ => return Err(format!("unknown sampler `{sampler_type}`")),
+            };
+            let emission_samples = table_get_default(table, "emission_samples", Sampling::default().emission_samples)?;
+            Ok(Sampling {
+                samples, adaptive, sampler,
+                emission_samples,
+            })
+        })
+    }
+}
+
+/// Either `denoise = true` for the default settings or a table overriding
+/// some of them.
+impl FromToml for Option<Denoiser> {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `denoise` definition";
+
+        ret_obj(err, || {
+            let default = Denoiser::default();
+            let table = match toml {
+                Value::Boolean(enabled) => return Ok(enabled.then_some(default)),
+                _ => get_table(toml)?,
+            };
+            Ok(Some(Denoiser {
+                iterations: table_get_default(table, "iterations", default.iterations)?,
+                sigma_color: table_get_default(table, "sigma_color", default.sigma_color)?,
+                sigma_normal: table_get_default(table, "sigma_normal", default.sigma_normal)?,
+                sigma_depth: table_get_default(table, "sigma_depth", default.sigma_depth)?,
+                sigma_albedo: table_get_default(table, "sigma_albedo", default.sigma_albedo)?,
+            }))
+        })
+    }
+}
+
+impl FromToml for PostProcess {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `post` definition";
+
+        ret_obj(err, || {
+            let table = get_table(toml)?;
+            Ok(PostProcess::new(table_get_default(table, "stages", Vec::new())?))
+        })
+    }
+}
+
+impl FromToml for PostStage {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let table = get_table(toml)?;
+
+        let stage_type: String = table_get(table, "type")?;
+        match stage_type.as_str() {
+            "exposure" => Ok(PostStage::Exposure {
+                stops: table_get(table, "stops")?,
+            }),
+            "gamma" => {
+                let gamma: f64 = table_get_default(table, "gamma", 2.2)?;
+                if gamma <= 0. {
+                    return Err(format!("`gamma` must be positive, got {gamma}"));
+                }
+                Ok(PostStage::Gamma { gamma })
+            }
+            "srgb" => Ok(PostStage::Srgb),
+            "contrast" => Ok(PostStage::Contrast {
+                amount: table_get(table, "amount")?,
+            }),
+            "saturation" => Ok(PostStage::Saturation {
+                amount: table_get(table, "amount")?,
+            }),
+            "vignette" => Ok(PostStage::Vignette {
+                strength: table_get_default(table, "strength", 0.5)?,
+            }),
+            "bloom" => {
+                let threshold: f64 = table_get_default(table, "threshold", 0.8)?;
+                if threshold < 0. {
+                    return Err(format!("`threshold` must not be negative, got {threshold}"));
+                }
+                Ok(PostStage::Bloom {
+                    threshold,
+                    strength: table_get_default(table, "strength", 1.)?,
+                    radius: table_get_default(table, "radius", 8)?,
+                })
+            }
+            "sharpen" => Ok(PostStage::Sharpen {
+                amount: table_get_default(table, "amount", 0.5)?,
+            }),
+            "chromatic_aberration" => Ok(PostStage::ChromaticAberration {
+                pixels: table_get_default(table, "pixels", 2.)?,
+            }),
+            _ => Err(format!("unknown post stage `{stage_type}`")),
+        }
+    }
+}
+
+/// `dither` is one of `none`, `bayer` (with `bayer_size`), `blue-noise` or
+/// `floyd-steinberg`.
+impl FromToml for Quantizer {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let err = "error in `quantize` definition";
+
+        ret_obj(err, || {
+            let table = get_table(toml)?;
+            let dither: String = table_get_default(table, "dither", "none".into())?;
+            let dither = match dither.as_str() {
+                "none" => Dither::None,
+                "bayer" => Dither::Bayer { size: table_get_default(table, "bayer_size", 4)? },
+                "blue-noise" => Dither::BlueNoise,
+                "floyd-steinberg" => Dither::FloydSteinberg,
+                _ => return Err(format!("unknown dither `{dither}`")),
+            };
+            Ok(Quantizer {
+                dither,
+                palette: table_get_default(table, "palette", None)?,
+            })
+        })
+    }
+}
+
+/// `"vga"` for the 16 CGA/EGA colors, a number of levels per channel, or a
+/// list of colors.
+impl FromToml for Palette {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        match toml {
+            Value::String(name) if name == "vga" => Ok(Palette::vga()),
+            Value::String(name) => Err(format!("unknown palette `{name}`")),
+            Value::Integer(levels @ 2..=16) => Ok(Palette::uniform(*levels as u32)),
+            Value::Integer(levels) => Err(format!("expected 2 to 16 levels, got {levels}")),
+            _ => Ok(Palette::new(Vec::from_toml(toml)?)),
+        }
+        .map_err(|e| format!("error in `palette` definition:\n{e}"))
+    }
+}
+
+impl FromToml for Aov {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        let name = String::from_toml(toml)?;
+        match name.as_str() {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "object_id" => Ok(Aov::ObjectId),
+            "albedo" => Ok(Aov::Albedo),
+            "position" => Ok(Aov::Position),
+            "samples" => Ok(Aov::SampleCount),
+            _ => Err(format!("unknown aov `{name}`"))
+        }
+    }
+}
+
 impl FromToml for Point {
     fn from_toml(toml: &Value) -> Result<Self, String> {
         <[f64; 3]>::from_toml(toml)
-            .map(|a| Point::from(a))
+            .map(Point::from)
             .map_err(|e| format!("error in `point` definition:\n{e}"))
     }
 }
 impl FromToml for Vector {
     fn from_toml(toml: &Value) -> Result<Self, String> {
         <[f64; 3]>::from_toml(toml)
-            .map(|a| Vector::from(a))
+            .map(Vector::from)
             .map_err(|e| format!("error in `vector` definition:\n{e}"))
     }
 }
+/// An `[r, g, b]` array, a hex string like `"#ff8800"` or a CSS color name.
 impl FromToml for Color {
     fn from_toml(toml: &Value) -> Result<Self, String> {
-        <[u8; 3]>::from_toml(toml)
-            .map(|a| Color::from(a))
-            .map_err(|e| format!("error in `color` definition:\n{e}"))
+        match toml {
+            Value::String(s) => s.parse(),
+            _ => <[u8; 3]>::from_toml(toml).map(Color::from),
+        }
+        .map_err(|e| format!("error in `color` definition:\n{e}"))
+    }
+}
+
+impl<T: FromToml> FromToml for Option<T> {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        T::from_toml(toml).map(Some)
     }
 }
 
@@ -224,7 +727,6 @@ impl<T: FromToml> FromToml for Vec<T> {
 impl<T: FromToml, const N: usize> FromToml for [T; N] {
 
     fn from_toml(toml: &Value) -> Result<Self, String> {
-        use std::mem::MaybeUninit;
         let err = "error in array definition";
         let sized_array_err = format!("expected `{N}` sized array");
 
@@ -233,13 +735,11 @@ impl<T: FromToml, const N: usize> FromToml for [T; N] {
                 if ar.len() != N {
                     return Err(sized_array_err);
                 }
-                let mut out: [T; N] = unsafe {
-                    MaybeUninit::uninit().assume_init()
-                };
+                let mut out = Vec::with_capacity(N);
                 for (i, v) in ar.iter().enumerate() {
-                    out[i] = T::from_toml(v).map_err(|e| format!("{err}:\n{e} at index `{i}`"))?;
+                    out.push(T::from_toml(v).map_err(|e| format!("{err}:\n{e} at index `{i}`"))?);
                 }
-                Ok(out)
+                out.try_into().map_err(|_| sized_array_err)
             },
             _ => Err(sized_array_err),
         }
@@ -255,6 +755,15 @@ impl FromToml for String {
     }
 }
 
+impl FromToml for bool {
+    fn from_toml(toml: &Value) -> Result<Self, String> {
+        match *toml {
+            Value::Boolean(b) => Ok(b),
+            _ => Err("expected boolean".into())
+        }
+    }
+}
+
 impl FromToml for f64 {
     fn from_toml(toml: &Value) -> Result<Self, String> {
         match *toml {
@@ -306,4 +815,150 @@ mod tests {
         <[u32; 3]>::from_toml(&toml::Value::Array(vec![toml::Value::Integer(2); 5])).unwrap_err();
         <[u32; 3]>::from_toml(&toml::Value::Array(vec![toml::Value::Integer(-1); 3])).unwrap_err();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn instances_share_geometry() {
+        let scene = r#"
+            [canvas]
+            width = 4
+            height = 4
+            [camera]
+
+            [geometries.ball]
+            [[geometries.ball.spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            color = [255, 0, 0]
+
+            [[instances]]
+            geometry = "ball"
+            [[instances]]
+            geometry = "ball"
+            translate = [3, 0, 0]
+            color = [0, 255, 0]
+        "#;
+        let rt = RayTracer::from_description(scene).unwrap();
+        assert!(Arc::ptr_eq(&rt.instances[0].geometry, &rt.instances[1].geometry));
+        assert_eq!(rt.instances[1].material.as_ref().map(|m| m.color), Some(color(0, 255, 0)));
+
+        let partial = scene.replace("color = [0, 255, 0]", "specular = 10");
+        assert!(RayTracer::from_description(&partial).unwrap_err().contains("`specular` needs a `color` or `material`"));
+
+        let cycle = scene.replace("[[geometries.ball.spheres]]", "[[geometries.ball.instances]]\ngeometry = \"ball\"\n[[geometries.ball.spheres]]");
+        assert!(RayTracer::from_description(&cycle).unwrap_err().contains("contains itself"));
+    }
+
+    #[test]
+    fn materials() {
+        let scene = r#"
+            [canvas]
+            width = 4
+            height = 4
+            [camera]
+
+            [materials.mirror]
+            color = [255, 255, 255]
+            reflective = 1
+
+            [[spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            material = "mirror"
+            [[spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            color = [255, 0, 0]
+            specular = 10
+        "#;
+        let rt = RayTracer::from_description(scene).unwrap();
+        assert_eq!(rt.spheres[0].material.reflective, 1.);
+        assert_eq!(rt.spheres[1].material, Material { specular: 10., ..Material::new(color(255, 0, 0)) });
+
+        let unknown = scene.replace("\"mirror\"", "\"glass\"");
+        assert!(RayTracer::from_description(&unknown).unwrap_err().contains("unknown material `glass`"));
+    }
+
+    #[test]
+    fn color_strings() {
+        let scene = r##"
+            background = "ff8800"
+            [canvas]
+            width = 4
+            height = 4
+            [camera]
+            [[spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            color = "#f00"
+            [[spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            color = "Tomato"
+        "##;
+        let rt = RayTracer::from_description(scene).unwrap();
+        assert_eq!(rt.background, Some(color(255, 136, 0)));
+        assert_eq!(rt.spheres[0].material.color, color(255, 0, 0));
+        assert_eq!(rt.spheres[1].material.color, color(255, 99, 71));
+
+        let unknown = scene.replace("\"Tomato\"", "\"whiteish\"");
+        assert!(RayTracer::from_description(&unknown).unwrap_err().contains("unknown color `whiteish`"));
+    }
+
+    #[test]
+    fn post_stages() {
+        let scene = r#"
+            [canvas]
+            width = 2
+            height = 2
+            [camera]
+            [post]
+            stages = [{ type = "exposure", stops = -2 }]
+        "#;
+        let rt = RayTracer::from_description(scene).unwrap();
+        assert_eq!(rt.post.stages, [PostStage::Exposure { stops: -2. }]);
+
+        let gamma = scene.replace("type = \"exposure\", stops = -2", "type = \"gamma\", gamma = 0");
+        assert!(RayTracer::from_description(&gamma).unwrap_err().contains("`gamma` must be positive"));
+        let bloom = scene.replace("type = \"exposure\", stops = -2", "type = \"bloom\", threshold = -1");
+        assert!(RayTracer::from_description(&bloom).unwrap_err().contains("`threshold` must not be negative"));
+    }
+
+    #[test]
+    fn light_links() {
+        let scene = r#"
+            [canvas]
+            width = 1
+            height = 1
+            [camera]
+            [[spheres]]
+            position = [0, 0, 0]
+            radius = 1
+            color = [0, 0, 0]
+            emission = [255, 255, 255]
+            exclude = ["hero"]
+            [[lights]]
+            type = "ambient"
+            intensity = 1
+            include = ["props", "hero"]
+        "#;
+        let rt = RayTracer::from_description(scene).unwrap();
+        assert_eq!(rt.lights[0].links(), &LightLinks { include: vec!["props".into(), "hero".into()], exclude: Vec::new() });
+        assert_eq!(rt.spheres[0].emission_links.exclude, ["hero"]);
+    }
+
+    #[test]
+    fn quantize() {
+        let scene = r#"
+            [canvas]
+            width = 1
+            height = 1
+            [camera]
+            [quantize]
+            dither = "bayer"
+        "#;
+        for (name, dither) in [("bayer", Dither::Bayer { size: 4 }), ("blue-noise", Dither::BlueNoise), ("floyd-steinberg", Dither::FloydSteinberg)] {
+            let rt = RayTracer::from_description(&scene.replace("bayer", name)).unwrap();
+            assert_eq!(rt.quantize.dither, dither);
+        }
+    }
+}
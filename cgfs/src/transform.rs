@@ -0,0 +1,172 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Rad};
+
+use crate::math::*;
+
+/// Affine transform from object space to world space, stored together with
+/// its inverse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    identity: bool,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+            identity: true,
+        }
+    }
+
+    /// Returns `None` if the matrix can't be inverted.
+    pub fn from_matrix(matrix: Matrix4<f64>) -> Option<Self> {
+        let inverse = matrix.invert()?;
+        Some(Self {
+            matrix,
+            inverse,
+            identity: matrix == Matrix4::identity(),
+        })
+    }
+
+    pub fn translate(v: Vector) -> Self {
+        Self {
+            matrix: Matrix4::from_translation(v),
+            inverse: Matrix4::from_translation(-v),
+            identity: v == vector(0., 0., 0.),
+        }
+    }
+
+    /// Rotation by `degrees` around each axis, the same as a camera rotation,
+    /// see [`rotation`].
+    pub fn rotate(degrees: Vector) -> Self {
+        let m = Matrix4::from(rotation(degrees));
+        Self {
+            matrix: m,
+            // rotations are orthogonal
+            inverse: m.transpose(),
+            identity: degrees == vector(0., 0., 0.),
+        }
+    }
+
+    /// Non-uniform scale. Returns `None` if any factor is zero.
+    pub fn scale(factors: Vector) -> Option<Self> {
+        if factors.x == 0. || factors.y == 0. || factors.z == 0. {
+            return None;
+        }
+        Some(Self {
+            matrix: Matrix4::from_nonuniform_scale(factors.x, factors.y, factors.z),
+            inverse: Matrix4::from_nonuniform_scale(1. / factors.x, 1. / factors.y, 1. / factors.z),
+            identity: factors == vector(1., 1., 1.),
+        })
+    }
+
+    /// Scale, then rotate, then translate.
+    pub fn from_parts(translate: Vector, rotate: Vector, scale: Vector) -> Option<Self> {
+        Some(Self::translate(translate) * Self::rotate(rotate) * Self::scale(scale)?)
+    }
+
+    pub fn matrix(&self) -> &Matrix4<f64> {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+            identity: self.identity,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    pub fn point(&self, p: Point) -> Point {
+        if self.identity {
+            return p;
+        }
+        Point::from_homogeneous(self.matrix * p.to_homogeneous())
+    }
+
+    pub fn vector(&self, v: Vector) -> Vector {
+        if self.identity {
+            return v;
+        }
+        (self.matrix * v.extend(0.)).truncate()
+    }
+
+    /// Maps an object space normal to world space. The result is not
+    /// normalized.
+    pub fn normal(&self, n: Vector) -> Vector {
+        if self.identity {
+            return n;
+        }
+        let inverse = &self.inverse;
+        let m = Matrix3::from_cols(inverse.x.truncate(), inverse.y.truncate(), inverse.z.truncate());
+        m.transpose() * n
+    }
+}
+
+/// Rotation by `degrees` around each axis, applied z, then x, then y.
+/// Positive angles turn clockwise when looking down the axis towards the
+/// origin, so `[0, 90, 0]` turns `+z` into `-x`.
+pub(crate) fn rotation(degrees: Vector) -> Matrix3<f64> {
+    let angle = |d: f64| Rad(-d.to_radians());
+    Matrix3::from_angle_y(angle(degrees.y))
+        * Matrix3::from_angle_x(angle(degrees.x))
+        * Matrix3::from_angle_z(angle(degrees.z))
+}
+
+/// `a * b` applies `b` first.
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+    fn mul(self, rhs: Transform) -> Self::Output {
+        if self.identity {
+            return rhs;
+        }
+        if rhs.identity {
+            return self;
+        }
+        Self {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+            identity: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vector, b: Vector) -> bool {
+        (a - b).magnitude() < 1e-9
+    }
+
+    #[test]
+    fn compose() {
+        let t = Transform::from_parts(vector(1., 2., 3.), vector(0., 90., 0.), vector(2., 1., 1.)).unwrap();
+        let p = t.point(point(1., 0., 0.));
+        assert!(close(p.to_vec(), vector(1., 2., 5.)));
+        assert!(close(t.inverse().point(p).to_vec(), vector(1., 0., 0.)));
+        assert!(close(t.vector(vector(0., 1., 0.)), vector(0., 1., 0.)));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = Transform::from_parts(vector(0., 0., 0.), vector(30., 20., 10.), vector(3., 1., 0.5)).unwrap();
+        // tangent and normal of the unit sphere at some point
+        let n = vector(1., 1., 0.).normalize();
+        let tangent = vector(-1., 1., 0.);
+        assert!(t.normal(n).dot(t.vector(tangent)).abs() < 1e-9);
+    }
+}
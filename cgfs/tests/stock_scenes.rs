@@ -0,0 +1,25 @@
+//! The scenes shipped with the repository render exactly as they did before
+//! the post-process pipeline, pixel for pixel.
+#![cfg(feature = "scene")]
+
+use cgfs::RayTracer;
+
+fn assert_matches_baseline(scene: &str, baseline: &str) {
+    let mut rt = RayTracer::from_description(scene).unwrap();
+    let image = rt.render();
+    let path = format!("{}/tests/data/{baseline}", env!("CARGO_MANIFEST_DIR"));
+    let expected = image::open(&path).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), expected.dimensions());
+    let differing = image.pixels().zip(expected.pixels()).filter(|(a, b)| a != b).count();
+    assert_eq!(differing, 0, "{baseline}: {differing} pixels differ");
+}
+
+#[test]
+fn scene_matches_baseline() {
+    assert_matches_baseline(include_str!("../../scene.toml"), "scene.png");
+}
+
+#[test]
+fn web_scene_matches_baseline() {
+    assert_matches_baseline(include_str!("../../web/scene.toml"), "web_scene.png");
+}
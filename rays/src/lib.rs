@@ -1,4 +1,4 @@
-use cgfs::RayTracer;
+use cgfs::{RayTracer, RenderStats};
 use winit::{
     window::{Window, WindowBuilder},
 };
@@ -40,7 +40,7 @@ impl<State, Setup, Update> Rays<State, Setup, Update>
         let window = WindowBuilder::new()
             .with_inner_size(winit::dpi::LogicalSize::new(self.rt.image().width(), self.rt.image().height()))
             .with_resizable(true)
-            .with_title("Rays")
+            .with_title(title(&self.rt))
             .build(&event_loop)
             .unwrap();
 
@@ -56,6 +56,21 @@ pub struct Ctx<'a> {
     rt: &'a mut RayTracer,
 }
 
+impl Ctx<'_> {
+    /// Statistics of the last frame.
+    pub fn render_stats(&self) -> Option<RenderStats> {
+        self.rt.render_stats()
+    }
+}
+
+/// Window title with the ray count and time of the last render.
+fn title(rt: &RayTracer) -> String {
+    match rt.render_stats() {
+        Some(stats) => format!("Rays - {} primary rays in {:.1?}", stats.primary_rays, stats.tracing_time),
+        None => "Rays".into(),
+    }
+}
+
 
 pub enum Msg {
     Nil,
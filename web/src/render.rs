@@ -24,23 +24,19 @@ pub struct Render {
 fn render(scene_desc: &str, canvas_width: u32, canvas_height: u32) -> Result<ImageData, String> {
     web_sys::console::log_1(&format!("scene_desc: {scene_desc}").into());
     let mut rt = RayTracer::from_description(scene_desc)?;
+    rt.enable_stats();
+    rt.render();
     let img = image::imageops::resize(
-        rt.render(),
+        &rt.canvas.to_rgba(),
         canvas_width,
         canvas_height,
         image::imageops::FilterType::Nearest);
 
-    let data: Vec<u8> = img
-        .pixels()
-        .map(|p| {
-            [p.0[0], p.0[1], p.0[2], 255]
-        })
-        .flatten()
-        .collect();
-
-    let width = img.width();
+    if let Some(stats) = rt.render_stats() {
+        web_sys::console::log_1(&format!("render stats:\n{stats}").into());
+    }
 
-    let image_data = ImageData::new_with_u8_clamped_array(Clamped(&data), width);
+    let image_data = ImageData::new_with_u8_clamped_array(Clamped(img.as_raw().as_slice()), img.width());
 
     image_data.map_err(|e| {
         let mut error = "Error creating ImageData".to_string();